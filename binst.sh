@@ -51,6 +51,7 @@ if [[ ! -f "${ENV_FILE}" ]]; then
 #   OPTIONS="--port 8051 --verbose 0"
 #   OPTIONS="--external --port 8051 --verbose 1"
 #   OPTIONS="--external --allow-sudo --port 8051 --verbose 1"
+#   OPTIONS="--external --tls-cert /etc/jelly-fpga-server/server.crt --tls-key /etc/jelly-fpga-server/server.key --tls-client-ca /etc/jelly-fpga-server/ca.crt"
 # See: ./jelly-fpga-server --help
 
 OPTIONS="--port 8051 --external --verbose 0"
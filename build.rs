@@ -1,4 +1,10 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_prost_build::compile_protos("./protos/jelly_fpga_control.proto")?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+    tonic_prost_build::configure()
+        .file_descriptor_set_path(out_dir.join("jelly_fpga_control_descriptor.bin"))
+        .compile_protos(&["./protos/jelly_fpga_control.proto"], &["./protos"])?;
     Ok(())
 }
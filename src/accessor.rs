@@ -6,21 +6,173 @@ use jelly_mem_access::UdmabufAccessor;
 use jelly_mem_access::UioAccessor;
 use std::collections::HashMap;
 use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 use std::result::Result;
+use std::sync::{Arc, Weak};
+
+use crate::irq::UioIrq;
+use crate::session::SessionId;
 
 pub type Id = u32;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessorError {
+    InvalidId(Id),
+    InvalidSize(usize),
+    // wire values, they may not fit in usize on 32-bit targets
+    OutOfRange {
+        id: Id,
+        offset: u64,
+        len: u64,
+        size: usize,
+    },
+    Misaligned {
+        id: Id,
+        offset: usize,
+        align: usize,
+    },
+    RegOverflow {
+        id: Id,
+        reg: usize,
+        unit: usize,
+    },
+    NotUio(Id),
+    Open {
+        target: String,
+        kind: Option<io::ErrorKind>,
+        message: String,
+    },
+}
+
+impl AccessorError {
+    fn open(target: &str, err: Box<dyn Error>) -> Self {
+        AccessorError::Open {
+            target: target.to_string(),
+            kind: err.downcast_ref::<io::Error>().map(|e| e.kind()),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for AccessorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessorError::InvalidId(id) => write!(f, "invalid accessor id: {}", id),
+            AccessorError::InvalidSize(size) => write!(f, "invalid access size: {}", size),
+            AccessorError::OutOfRange {
+                id,
+                offset,
+                len,
+                size,
+            } => write!(
+                f,
+                "access out of range on accessor {}: offset {:#x} + {} exceeds size {:#x}",
+                id, offset, len, size
+            ),
+            AccessorError::Misaligned { id, offset, align } => write!(
+                f,
+                "misaligned access on accessor {}: offset {:#x} is not {}-byte aligned",
+                id, offset, align
+            ),
+            AccessorError::RegOverflow { id, reg, unit } => write!(
+                f,
+                "register offset overflow on accessor {}: reg {} * unit {}",
+                id, reg, unit
+            ),
+            AccessorError::NotUio(id) => write!(f, "accessor {} is not a UIO device", id),
+            AccessorError::Open {
+                target, message, ..
+            } => write!(f, "failed to open {}: {}", target, message),
+        }
+    }
+}
+
+impl Error for AccessorError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchKind {
+    Read,
+    Write(u64),
+    Modify { data: u64, mask: u64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOp {
+    pub id: Id,
+    pub reg: bool,
+    pub address: u64,
+    pub size: u64,
+    pub kind: BatchKind,
+}
+
+// byte width of an integer access, 0 meaning the native word
+fn access_width(size: usize) -> Result<usize, AccessorError> {
+    match size {
+        0 => Ok(std::mem::size_of::<usize>()),
+        1 | 2 | 4 | 8 => Ok(size),
+        _ => Err(AccessorError::InvalidSize(size)),
+    }
+}
+
+/// Anonymous memory standing in for a device region, shared by every
+/// accessor a session opens with the same name.
+#[derive(Debug)]
+struct SimMemory {
+    fd: OwnedFd,
+    size: usize,
+    phys_addr: usize,
+}
+
+impl SimMemory {
+    fn new(name: &str, size: usize, phys_addr: usize) -> io::Result<Self> {
+        let c_name =
+            CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let raw = unsafe { libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC) };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let file = File::from(unsafe { OwnedFd::from_raw_fd(raw) });
+        file.set_len(size as u64)?;
+        Ok(SimMemory {
+            fd: file.into(),
+            size,
+            phys_addr,
+        })
+    }
+
+    // the memfd is mapped through procfs like any other file
+    fn path(&self) -> String {
+        format!("/proc/self/fd/{}", self.fd.as_raw_fd())
+    }
+}
+
 #[derive(Debug)]
 enum AccessorEnum {
-    MmapAccessor(MmapAccessor<u8>),
-    UioAccessor(UioAccessor<u8>),
-    UdmabufAccessor(UdmabufAccessor<u8>),
+    Mmap(MmapAccessor<u8>),
+    // the interrupt is opened with the accessor so none are missed between two waits
+    Uio(UioAccessor<u8>, Arc<UioIrq>),
+    Udmabuf(UdmabufAccessor<u8>),
+    // the offset into the memory gives the fake physical address
+    Sim(MmapAccessor<u8>, Arc<SimMemory>, usize),
+}
+
+#[derive(Debug)]
+struct Entry {
+    accessor: AccessorEnum,
+    unit: usize,
+    owner: SessionId,
+    shared: bool,
 }
 
 #[derive(Debug)]
 pub struct Accessor {
     id: Id,
-    map: HashMap<Id, (AccessorEnum, usize)>,
+    map: HashMap<Id, Entry>,
+    sim: HashMap<(SessionId, String), Weak<SimMemory>>,
 }
 
 impl Default for Accessor {
@@ -28,6 +180,7 @@ impl Default for Accessor {
         Self {
             id: 1,
             map: HashMap::new(),
+            sim: HashMap::new(),
         }
     }
 }
@@ -37,17 +190,26 @@ impl Accessor {
         Accessor {
             id: 1,
             map: HashMap::new(),
+            sim: HashMap::new(),
         }
     }
 
-    fn add_accessor(&mut self, accessor: AccessorEnum, unit: usize) -> Id {
+    fn add_accessor(&mut self, accessor: AccessorEnum, unit: usize, owner: SessionId) -> Id {
         let unit = if unit == 0 {
             std::mem::size_of::<usize>()
         } else {
             unit
         };
         let id = self.id;
-        self.map.insert(id, (accessor, unit));
+        self.map.insert(
+            id,
+            Entry {
+                accessor,
+                unit,
+                owner,
+                shared: false,
+            },
+        );
         self.id += 1;
         id
     }
@@ -58,15 +220,24 @@ impl Accessor {
         offset: usize,
         size: usize,
         unit: usize,
-    ) -> Result<Id, Box<dyn Error>> {
-        let accessor = MmapAccessor::<u8>::new(path, offset, size)?;
-        let id = self.add_accessor(AccessorEnum::MmapAccessor(accessor), unit);
+        owner: SessionId,
+    ) -> Result<Id, AccessorError> {
+        let accessor = MmapAccessor::<u8>::new(path, offset, size)
+            .map_err(|e| AccessorError::open(path, e))?;
+        let id = self.add_accessor(AccessorEnum::Mmap(accessor), unit, owner);
         Ok(id)
     }
 
-    pub fn open_uio(&mut self, name: &str, unit: usize) -> Result<Id, Box<dyn Error>> {
-        let accessor = UioAccessor::<u8>::new_with_name(name)?;
-        let id = self.add_accessor(AccessorEnum::UioAccessor(accessor), unit);
+    pub fn open_uio(
+        &mut self,
+        name: &str,
+        unit: usize,
+        owner: SessionId,
+    ) -> Result<Id, AccessorError> {
+        let accessor = UioAccessor::<u8>::new_with_name(name)
+            .map_err(|e| AccessorError::open(name, e))?;
+        let irq = UioIrq::open(name).map_err(|e| AccessorError::open(name, e.into()))?;
+        let id = self.add_accessor(AccessorEnum::Uio(accessor, Arc::new(irq)), unit, owner);
         Ok(id)
     }
 
@@ -75,33 +246,187 @@ impl Accessor {
         name: &str,
         cache_enable: bool,
         unit: usize,
-    ) -> Result<Id, Box<dyn Error>> {
-        let accessor = UdmabufAccessor::<u8>::new(name, cache_enable)?;
-        let id = self.add_accessor(AccessorEnum::UdmabufAccessor(accessor), unit);
+        owner: SessionId,
+    ) -> Result<Id, AccessorError> {
+        let accessor = UdmabufAccessor::<u8>::new(name, cache_enable)
+            .map_err(|e| AccessorError::open(name, e))?;
+        let id = self.add_accessor(AccessorEnum::Udmabuf(accessor), unit, owner);
         Ok(id)
     }
 
-    fn accessor(&self, id: Id) -> Result<(&dyn MemAccess, usize), Box<dyn Error>> {
-        let (accessor, unit) = self.map.get(&id).ok_or("Invalid id")?;
-        match accessor {
-            AccessorEnum::MmapAccessor(acc) => return Ok((acc, *unit)),
-            AccessorEnum::UioAccessor(acc) => return Ok((acc, *unit)),
-            AccessorEnum::UdmabufAccessor(acc) => return Ok((acc, *unit)),
+    // Opening a name that is still open elsewhere maps the same memory, so
+    // size and physical address may be left 0 to take the existing ones.
+    pub fn open_sim(
+        &mut self,
+        name: &str,
+        size: usize,
+        phys_addr: usize,
+        unit: usize,
+        owner: SessionId,
+    ) -> Result<Id, AccessorError> {
+        let invalid = |message: String| AccessorError::Open {
+            target: name.to_string(),
+            kind: Some(io::ErrorKind::InvalidInput),
+            message,
+        };
+        // regions are private to the opening session, other sessions reach
+        // them only through accessors made available with set_shared
+        self.sim.retain(|_, memory| memory.strong_count() > 0);
+        let key = (owner, name.to_string());
+        let memory = match self.sim.get(&key).and_then(Weak::upgrade) {
+            Some(memory) => {
+                if size > memory.size {
+                    return Err(invalid(format!(
+                        "size {:#x} exceeds the open region of {:#x}",
+                        size, memory.size
+                    )));
+                }
+                if phys_addr != 0 && phys_addr != memory.phys_addr {
+                    return Err(invalid(format!(
+                        "region is open at physical address {:#x}",
+                        memory.phys_addr
+                    )));
+                }
+                memory
+            }
+            None => {
+                if size == 0 {
+                    return Err(invalid("size must not be 0".to_string()));
+                }
+                let memory = SimMemory::new(name, size, phys_addr)
+                    .map_err(|e| AccessorError::open(name, e.into()))?;
+                let memory = Arc::new(memory);
+                self.sim.insert(key, Arc::downgrade(&memory));
+                memory
+            }
+        };
+        let size = if size == 0 { memory.size } else { size };
+        let accessor = MmapAccessor::<u8>::new(&memory.path(), 0, size)
+            .map_err(|e| AccessorError::open(name, e))?;
+        let id = self.add_accessor(AccessorEnum::Sim(accessor, memory, 0), unit, owner);
+        Ok(id)
+    }
+
+    fn entry(&self, id: Id) -> Result<&Entry, AccessorError> {
+        self.map.get(&id).ok_or(AccessorError::InvalidId(id))
+    }
+
+    fn accessor(&self, id: Id) -> Result<(&dyn MemAccess, usize), AccessorError> {
+        let entry = self.entry(id)?;
+        let unit = entry.unit;
+        match &entry.accessor {
+            AccessorEnum::Mmap(acc) => Ok((acc, unit)),
+            AccessorEnum::Uio(acc, _) => Ok((acc, unit)),
+            AccessorEnum::Udmabuf(acc) => Ok((acc, unit)),
+            AccessorEnum::Sim(acc, ..) => Ok((acc, unit)),
             //        _ => return Err("Invalid accessor".into()),
         }
     }
 
-    pub fn addr(&self, id: Id) ->  Result<usize, Box<dyn Error>> {
+    // Every access goes through here so that the unsafe MemAccess calls only
+    // ever see ranges inside the mapping. Device registers (mmap/uio) fault on
+    // unaligned bus accesses, so those also require natural alignment;
+    // udmabuf is ordinary memory and does not. Simulated regions stand in for
+    // devices and check alignment like them.
+    fn checked(
+        &self,
+        id: Id,
+        offset: usize,
+        len: usize,
+        align: usize,
+    ) -> Result<&dyn MemAccess, AccessorError> {
+        let (accessor, _) = self.accessor(id)?;
+        let size = accessor.size();
+        if offset.checked_add(len).is_none_or(|end| end > size) {
+            return Err(AccessorError::OutOfRange {
+                id,
+                offset: offset as u64,
+                len: len as u64,
+                size,
+            });
+        }
+        let device = !matches!(self.entry(id)?.accessor, AccessorEnum::Udmabuf(_));
+        if device && align > 1 && accessor.addr().wrapping_add(offset) % align != 0 {
+            return Err(AccessorError::Misaligned { id, offset, align });
+        }
+        Ok(accessor)
+    }
+
+    /// Converts an offset or register index and a size sent by a client. On
+    /// 32-bit targets values past usize::MAX fail instead of wrapping into range.
+    pub fn wire_range(
+        &self,
+        id: Id,
+        offset: u64,
+        size: u64,
+    ) -> Result<(usize, usize), AccessorError> {
+        match (usize::try_from(offset), usize::try_from(size)) {
+            (Ok(offset), Ok(size)) => Ok((offset, size)),
+            _ => Err(AccessorError::OutOfRange {
+                id,
+                offset,
+                len: size,
+                size: self.size(id)?,
+            }),
+        }
+    }
+
+    pub fn wire_offset(&self, id: Id, offset: u64) -> Result<usize, AccessorError> {
+        self.wire_range(id, offset, 0).map(|(offset, _)| offset)
+    }
+
+    fn reg_offset(&self, id: Id, reg: usize) -> Result<usize, AccessorError> {
+        let unit = self.entry(id)?.unit;
+        reg.checked_mul(unit)
+            .ok_or(AccessorError::RegOverflow { id, reg, unit })
+    }
+
+    // handles of other sessions are reported as unknown ids unless shared
+    pub fn check_owner(&self, id: Id, session: SessionId) -> Result<(), AccessorError> {
+        let entry = self.entry(id)?;
+        if entry.owner == session || entry.shared {
+            Ok(())
+        } else {
+            Err(AccessorError::InvalidId(id))
+        }
+    }
+
+    pub fn set_shared(
+        &mut self,
+        id: Id,
+        session: SessionId,
+        shared: bool,
+    ) -> Result<(), AccessorError> {
+        match self.map.get_mut(&id) {
+            Some(entry) if entry.owner == session => {
+                entry.shared = shared;
+                Ok(())
+            }
+            _ => Err(AccessorError::InvalidId(id)),
+        }
+    }
+
+    pub fn uio_irq(&self, id: Id) -> Result<Arc<UioIrq>, AccessorError> {
+        match &self.entry(id)?.accessor {
+            AccessorEnum::Uio(_, irq) => Ok(irq.clone()),
+            _ => Err(AccessorError::NotUio(id)),
+        }
+    }
+
+    pub fn addr(&self, id: Id) ->  Result<usize, AccessorError> {
         let (accessor, _) = self.accessor(id)?;
         Ok(accessor.addr())
     }
 
-    pub fn size(&self, id: Id) ->  Result<usize, Box<dyn Error>> {
+    pub fn size(&self, id: Id) ->  Result<usize, AccessorError> {
         let (accessor, _) = self.accessor(id)?;
         Ok(accessor.size())
     }
 
-    pub fn phys_addr(&self, id: Id) ->  Result<usize, Box<dyn Error>> {
+    pub fn phys_addr(&self, id: Id) ->  Result<usize, AccessorError> {
+        if let AccessorEnum::Sim(_, memory, offset) = &self.entry(id)?.accessor {
+            return Ok(memory.phys_addr + offset);
+        }
         let (accessor, _) = self.accessor(id)?;
         Ok(accessor.phys_addr())
     }
@@ -112,32 +437,70 @@ impl Accessor {
         offset: usize,
         size: usize,
         unit: usize,
-    ) -> Result<Id, Box<dyn Error>> {
-        let (accessor, unit_org) = self.map.get(&id).ok_or("Invalid id")?;
-        let unit = if unit == 0 { *unit_org } else { unit };
-        let accessor: AccessorEnum = match accessor {
-            AccessorEnum::MmapAccessor(acc) => {
-                let acc = acc.subclone8(offset, size);
-                AccessorEnum::MmapAccessor(acc)
+        owner: SessionId,
+    ) -> Result<Id, AccessorError> {
+        let parent_size = self.size(id)?;
+        // size 0 clones the rest of the region
+        let len = if size == 0 { parent_size.saturating_sub(offset) } else { size };
+        if offset.checked_add(len).is_none_or(|end| end > parent_size) {
+            return Err(AccessorError::OutOfRange {
+                id,
+                offset: offset as u64,
+                len: len as u64,
+                size: parent_size,
+            });
+        }
+        let entry = self.entry(id)?;
+        let unit = if unit == 0 { entry.unit } else { unit };
+        let accessor: AccessorEnum = match &entry.accessor {
+            AccessorEnum::Mmap(acc) => {
+                let acc = acc.subclone8(offset, len);
+                AccessorEnum::Mmap(acc)
             }
-            AccessorEnum::UioAccessor(acc) => {
-                let acc = acc.subclone8(offset, size);
-                AccessorEnum::UioAccessor(acc)
+            AccessorEnum::Uio(acc, irq) => {
+                let acc = acc.subclone8(offset, len);
+                AccessorEnum::Uio(acc, irq.clone())
             }
-            AccessorEnum::UdmabufAccessor(acc) => {
-                let acc = acc.subclone8(offset, size);
-                AccessorEnum::UdmabufAccessor(acc)
+            AccessorEnum::Udmabuf(acc) => {
+                let acc = acc.subclone8(offset, len);
+                AccessorEnum::Udmabuf(acc)
+            }
+            AccessorEnum::Sim(acc, memory, base) => {
+                let acc = acc.subclone8(offset, len);
+                AccessorEnum::Sim(acc, memory.clone(), base + offset)
             }
         };
-        let id = self.id;
-        self.map.insert(id, (accessor, unit));
-        self.id += 1;
-        Ok(id)
+        Ok(self.add_accessor(accessor, unit, owner))
     }
 
-    pub fn close(&mut self, id: Id) -> Result<(), Box<dyn Error>> {
-        self.map.remove(&id).ok_or("Invalid id")?;
-        Ok(())
+    pub fn close(&mut self, id: Id, session: SessionId) -> Result<(), AccessorError> {
+        match self.map.get(&id) {
+            Some(entry) if entry.owner == session => {
+                self.map.remove(&id);
+                Ok(())
+            }
+            _ => Err(AccessorError::InvalidId(id)),
+        }
+    }
+
+    pub fn close_owned(&mut self, session: SessionId) -> usize {
+        let count = self.map.len();
+        self.map.retain(|_, entry| entry.owner != session);
+        count - self.map.len()
+    }
+
+    pub fn count_by_kind(&self) -> [(&'static str, usize); 4] {
+        let mut counts = [("mmap", 0), ("uio", 0), ("udmabuf", 0), ("sim", 0)];
+        for entry in self.map.values() {
+            let index = match entry.accessor {
+                AccessorEnum::Mmap(_) => 0,
+                AccessorEnum::Uio(..) => 1,
+                AccessorEnum::Udmabuf(_) => 2,
+                AccessorEnum::Sim(..) => 3,
+            };
+            counts[index].1 += 1;
+        }
+        counts
     }
 
     pub fn close_all(&mut self) {
@@ -150,8 +513,9 @@ impl Accessor {
         offset: usize,
         data: u64,
         size: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<(), AccessorError> {
+        let width = access_width(size)?;
+        let accessor = self.checked(id, offset, width, width)?;
         unsafe {
             match size {
                 0 => accessor.write_mem_usize(offset, data as usize),
@@ -159,7 +523,7 @@ impl Accessor {
                 2 => accessor.write_mem_u16(offset, data as u16),
                 4 => accessor.write_mem_u32(offset, data as u32),
                 8 => accessor.write_mem_u64(offset, data),
-                _ => return Err("Invalid size".into()),
+                _ => return Err(AccessorError::InvalidSize(size)),
             };
         }
         Ok(())
@@ -171,8 +535,9 @@ impl Accessor {
         offset: usize,
         data: i64,
         size: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<(), AccessorError> {
+        let width = access_width(size)?;
+        let accessor = self.checked(id, offset, width, width)?;
         unsafe{
             match size {
                 0 => accessor.write_mem_isize(offset, data as isize),
@@ -180,7 +545,7 @@ impl Accessor {
                 2 => accessor.write_mem_i16(offset, data as i16),
                 4 => accessor.write_mem_i32(offset, data as i32),
                 8 => accessor.write_mem_i64(offset, data),
-                _ => return Err("Invalid size".into()),
+                _ => return Err(AccessorError::InvalidSize(size)),
             };
         }
         Ok(())
@@ -191,15 +556,16 @@ impl Accessor {
         id: Id,
         offset: usize,
         size: usize,
-    ) -> Result<u64, Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<u64, AccessorError> {
+        let width = access_width(size)?;
+        let accessor = self.checked(id, offset, width, width)?;
         let data = unsafe { match size {
             0 => accessor.read_mem_usize(offset) as u64,
             1 => accessor.read_mem_u8(offset) as u64,
             2 => accessor.read_mem_u16(offset) as u64,
             4 => accessor.read_mem_u32(offset) as u64,
             8 => accessor.read_mem_u64(offset),
-            _ => return Err("Invalid size".into()),
+            _ => return Err(AccessorError::InvalidSize(size)),
         }};
         Ok(data)
     }
@@ -209,15 +575,16 @@ impl Accessor {
         id: Id,
         offset: usize,
         size: usize,
-    ) -> Result<i64, Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<i64, AccessorError> {
+        let width = access_width(size)?;
+        let accessor = self.checked(id, offset, width, width)?;
         let data = unsafe {match size {
             0 => accessor.read_mem_isize(offset) as i64,
             1 => accessor.read_mem_i8(offset) as i64,
             2 => accessor.read_mem_i16(offset) as i64,
             4 => accessor.read_mem_i32(offset) as i64,
             8 => accessor.read_mem_i64(offset),
-            _ => return Err("Invalid size".into()),
+            _ => return Err(AccessorError::InvalidSize(size)),
         }};
         Ok(data)
     }
@@ -228,16 +595,18 @@ impl Accessor {
         reg: usize,
         data: u64,
         size: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
+    ) -> Result<(), AccessorError> {
+        let width = access_width(size)?;
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, width, width)?;
         unsafe {
             match size {
-                0 => accessor.write_mem_usize(reg * unit, data as usize),
-                1 => accessor.write_mem_u8(reg * unit, data as u8),
-                2 => accessor.write_mem_u16(reg * unit, data as u16),
-                4 => accessor.write_mem_u32(reg * unit, data as u32),
-                8 => accessor.write_mem_u64(reg * unit, data as u64),
-                _ => return Err("Invalid size".into()),
+                0 => accessor.write_mem_usize(offset, data as usize),
+                1 => accessor.write_mem_u8(offset, data as u8),
+                2 => accessor.write_mem_u16(offset, data as u16),
+                4 => accessor.write_mem_u32(offset, data as u32),
+                8 => accessor.write_mem_u64(offset, data),
+                _ => return Err(AccessorError::InvalidSize(size)),
             };
         }
         Ok(())
@@ -249,16 +618,18 @@ impl Accessor {
         reg: usize,
         data: i64,
         size: usize,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
+    ) -> Result<(), AccessorError> {
+        let width = access_width(size)?;
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, width, width)?;
         unsafe {
             match size {
-                0 => accessor.write_mem_isize(reg * unit, data as isize),
-                1 => accessor.write_mem_i8(reg * unit, data as i8),
-                2 => accessor.write_mem_i16(reg * unit, data as i16),
-                4 => accessor.write_mem_i32(reg * unit, data as i32),
-                8 => accessor.write_mem_i64(reg * unit, data),
-                _ => return Err("Invalid size".into()),
+                0 => accessor.write_mem_isize(offset, data as isize),
+                1 => accessor.write_mem_i8(offset, data as i8),
+                2 => accessor.write_mem_i16(offset, data as i16),
+                4 => accessor.write_mem_i32(offset, data as i32),
+                8 => accessor.write_mem_i64(offset, data),
+                _ => return Err(AccessorError::InvalidSize(size)),
             };
         }
         Ok(())
@@ -269,15 +640,17 @@ impl Accessor {
         id: Id,
         reg: usize,
         size: usize,
-    ) -> Result<u64, Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
+    ) -> Result<u64, AccessorError> {
+        let width = access_width(size)?;
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, width, width)?;
         let data = unsafe { match size {
-            0 => accessor.read_mem_usize(reg * unit) as u64,
-            1 => accessor.read_mem_u8(reg * unit) as u64,
-            2 => accessor.read_mem_u16(reg * unit) as u64,
-            4 => accessor.read_mem_u32(reg * unit) as u64,
-            8 => accessor.read_mem_u64(reg * unit),
-            _ => return Err("Invalid size".into()),
+            0 => accessor.read_mem_usize(offset) as u64,
+            1 => accessor.read_mem_u8(offset) as u64,
+            2 => accessor.read_mem_u16(offset) as u64,
+            4 => accessor.read_mem_u32(offset) as u64,
+            8 => accessor.read_mem_u64(offset),
+            _ => return Err(AccessorError::InvalidSize(size)),
         }};
         Ok(data)
     }
@@ -287,15 +660,17 @@ impl Accessor {
         id: Id,
         reg: usize,
         size: usize,
-    ) -> Result<i64, Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
+    ) -> Result<i64, AccessorError> {
+        let width = access_width(size)?;
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, width, width)?;
         let data = unsafe { match size {
-            0 => accessor.read_mem_isize(reg * unit) as i64,
-            1 => accessor.read_mem_i8(reg * unit) as i64,
-            2 => accessor.read_mem_i16(reg * unit) as i64,
-            4 => accessor.read_mem_i32(reg * unit) as i64,
-            8 => accessor.read_mem_i64(reg * unit),
-            _ => return Err("Invalid size".into()),
+            0 => accessor.read_mem_isize(offset) as i64,
+            1 => accessor.read_mem_i8(offset) as i64,
+            2 => accessor.read_mem_i16(offset) as i64,
+            4 => accessor.read_mem_i32(offset) as i64,
+            8 => accessor.read_mem_i64(offset),
+            _ => return Err(AccessorError::InvalidSize(size)),
         }};
         Ok(data)
     }
@@ -305,8 +680,8 @@ impl Accessor {
         id: Id,
         offset: usize,
         data: f32,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<(), AccessorError> {
+        let accessor = self.checked(id, offset, 4, 4)?;
         unsafe { accessor.write_mem_f32(offset, data); }
         Ok(())
     }
@@ -316,8 +691,8 @@ impl Accessor {
         id: Id,
         offset: usize,
         data: f64,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<(), AccessorError> {
+        let accessor = self.checked(id, offset, 8, 8)?;
         unsafe { accessor.write_mem_f64(offset, data); }
         Ok(())
     }
@@ -327,9 +702,10 @@ impl Accessor {
         id: Id,
         reg: usize,
         data: f32,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
-        unsafe { accessor.write_mem_f32(reg * unit, data); }
+    ) -> Result<(), AccessorError> {
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, 4, 4)?;
+        unsafe { accessor.write_mem_f32(offset, data); }
         Ok(())
     }
 
@@ -338,33 +714,36 @@ impl Accessor {
         id: Id,
         reg: usize,
         data: f64,
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
-        unsafe { accessor.write_mem_f64(reg * unit, data); }
+    ) -> Result<(), AccessorError> {
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, 8, 8)?;
+        unsafe { accessor.write_mem_f64(offset, data); }
         Ok(())
     }
 
-    pub unsafe fn read_mem_f32(&mut self, id: Id, offset: usize) -> Result<f32, Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    pub unsafe fn read_mem_f32(&mut self, id: Id, offset: usize) -> Result<f32, AccessorError> {
+        let accessor = self.checked(id, offset, 4, 4)?;
         let data = unsafe { accessor.read_mem_f32(offset) };
         Ok(data)
     }
 
-    pub unsafe fn read_mem_f64(&mut self, id: Id, offset: usize) -> Result<f64, Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    pub unsafe fn read_mem_f64(&mut self, id: Id, offset: usize) -> Result<f64, AccessorError> {
+        let accessor = self.checked(id, offset, 8, 8)?;
         let data = unsafe { accessor.read_mem_f64(offset) };
         Ok(data)
     }
 
-    pub unsafe fn read_reg_f32(&mut self, id: Id, reg: usize) -> Result<f32, Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
-        let data = unsafe { accessor.read_mem_f32(reg * unit)};
+    pub unsafe fn read_reg_f32(&mut self, id: Id, reg: usize) -> Result<f32, AccessorError> {
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, 4, 4)?;
+        let data = unsafe { accessor.read_mem_f32(offset)};
         Ok(data)
     }
 
-    pub unsafe fn read_reg_f64(&mut self, id: Id, reg: usize) -> Result<f64, Box<dyn Error>> {
-        let (accessor, unit) = self.accessor(id)?;
-        let data = unsafe { accessor.read_mem_f64(reg * unit) };
+    pub unsafe fn read_reg_f64(&mut self, id: Id, reg: usize) -> Result<f64, AccessorError> {
+        let offset = self.reg_offset(id, reg)?;
+        let accessor = self.checked(id, offset, 8, 8)?;
+        let data = unsafe { accessor.read_mem_f64(offset) };
         Ok(data)
     }
 
@@ -373,9 +752,9 @@ impl Accessor {
         id: Id,
         offset: usize,
         data: &[u8],
-    ) -> Result<(), Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
-        unsafe { accessor.copy_from_u8(data.as_ptr(), offset as usize, data.len()); }
+    ) -> Result<(), AccessorError> {
+        let accessor = self.checked(id, offset, data.len(), 1)?;
+        unsafe { accessor.copy_from_u8(data.as_ptr(), offset, data.len()); }
         Ok(())
     }
 
@@ -384,10 +763,69 @@ impl Accessor {
         id: Id,
         offset: usize,
         size: usize,
-    ) -> Result<Vec<u8>, Box<dyn Error>> {
-        let (accessor, _) = self.accessor(id)?;
+    ) -> Result<Vec<u8>, AccessorError> {
+        let accessor = self.checked(id, offset, size, 1)?;
         let mut data = vec![0; size];
-        unsafe { accessor.copy_to_u8(offset as usize, data.as_mut_ptr(), size); }
+        unsafe { accessor.copy_to_u8(offset, data.as_mut_ptr(), size); }
         Ok(data)
     }
+
+    unsafe fn batch_read(&mut self, op: &BatchOp) -> Result<u64, AccessorError> {
+        let (address, size) = self.wire_range(op.id, op.address, op.size)?;
+        unsafe {
+            if op.reg {
+                self.read_reg_u(op.id, address, size)
+            } else {
+                self.read_mem_u(op.id, address, size)
+            }
+        }
+    }
+
+    unsafe fn batch_write(&mut self, op: &BatchOp, data: u64) -> Result<(), AccessorError> {
+        let (address, size) = self.wire_range(op.id, op.address, op.size)?;
+        unsafe {
+            if op.reg {
+                self.write_reg_u(op.id, address, data, size)
+            } else {
+                self.write_mem_u(op.id, address, data, size)
+            }
+        }
+    }
+
+    pub unsafe fn execute(
+        &mut self,
+        session: SessionId,
+        op: &BatchOp,
+    ) -> Result<u64, AccessorError> {
+        self.check_owner(op.id, session)?;
+        unsafe {
+            match op.kind {
+                BatchKind::Read => self.batch_read(op),
+                BatchKind::Write(data) => self.batch_write(op, data).map(|_| 0),
+                BatchKind::Modify { data, mask } => {
+                    let old = self.batch_read(op)?;
+                    self.batch_write(op, (old & !mask) | (data & mask))?;
+                    Ok(old)
+                }
+            }
+        }
+    }
+
+    pub unsafe fn execute_batch(
+        &mut self,
+        session: SessionId,
+        ops: &[BatchOp],
+        stop_on_error: bool,
+    ) -> Vec<Result<u64, AccessorError>> {
+        let mut results = Vec::with_capacity(ops.len());
+        for op in ops {
+            let result = unsafe { self.execute(session, op) };
+            let failed = result.is_err();
+            results.push(result);
+            if failed && stop_on_error {
+                break;
+            }
+        }
+        results
+    }
 }
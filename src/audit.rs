@@ -0,0 +1,246 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Serialize;
+use serde_json::Value;
+use tonic::{Request, Status};
+use tracing::warn;
+
+use crate::auth::{self, Identity};
+use crate::config::AuditConfig;
+use crate::logging;
+
+// audit records may contain register addresses, keep them away from other users
+const AUDIT_FILE_MODE: u32 = 0o640;
+
+/// Who issued a request, captured before the request is consumed.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    peer: Option<SocketAddr>,
+    identity: Identity,
+}
+
+impl Caller {
+    pub fn new<T>(request: &Request<T>) -> Self {
+        Caller {
+            peer: request.remote_addr(),
+            identity: auth::identity(request),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    peer: Option<SocketAddr>,
+    identity: &'a str,
+    role: String,
+    rpc: &'a str,
+    args: Value,
+    result: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    keep: u32,
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(AUDIT_FILE_MODE)
+        .open(path)
+}
+
+fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl Writer {
+    fn open(path: &Path, max_size: u64, keep: u32) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Writer {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            keep,
+        })
+    }
+
+    // audit.log -> audit.log.1 -> ... -> audit.log.<keep>, the oldest is dropped
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            for index in (1..self.keep).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    std::fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.max_size > 0 && self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Append-only JSON lines log of state-changing operations. Disabled unless a
+/// path is configured, in which case `record` is a no-op.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    writer: Option<Mutex<Writer>>,
+    writes: bool,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> io::Result<Self> {
+        let Some(path) = &config.path else {
+            return Ok(AuditLog::default());
+        };
+        let writer = Writer::open(Path::new(path), config.max_size, config.keep)
+            .map_err(|e| io::Error::new(e.kind(), format!("audit log {}: {}", path, e)))?;
+        Ok(AuditLog {
+            writer: Some(Mutex::new(writer)),
+            writes: config.writes,
+        })
+    }
+
+    pub fn record<T>(&self, caller: &Caller, rpc: &str, args: Value, result: &Result<T, Status>) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let (outcome, code, message) = match result {
+            Ok(_) => ("ok", None, None),
+            Err(status) => (
+                "error",
+                Some(format!("{:?}", status.code())),
+                Some(status.message()),
+            ),
+        };
+        let record = Record {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            peer: caller.peer,
+            identity: &caller.identity.name,
+            role: caller.identity.role.to_string(),
+            rpc,
+            args,
+            result: outcome,
+            code,
+            message,
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!(target: logging::SERVER, rpc, error = %e, "failed to encode audit record");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writer.write_line(&line) {
+            warn!(target: logging::SERVER, rpc, error = %e, "failed to write audit record");
+        }
+    }
+
+    // register and memory writes are only recorded when enabled, they can be very frequent
+    pub fn record_write<T>(
+        &self,
+        caller: &Caller,
+        rpc: &str,
+        args: Value,
+        result: &Result<T, Status>,
+    ) {
+        if self.writes {
+            self.record(caller, rpc, args, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn read(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rotation_renumbers_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut writer = Writer::open(&path, 4, 2).unwrap();
+        for line in ["a\n", "b\n", "c\n", "d\n", "e\n", "f\n", "g\n", "h\n"] {
+            writer.write_line(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(&path), "g\nh\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "e\nf\n");
+        assert_eq!(read(&rotated_path(&path, 2)), "c\nd\n");
+        assert!(!rotated_path(&path, 3).exists());
+    }
+
+    #[test]
+    fn rotation_continues_an_existing_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        std::fs::write(&path, "old\n").unwrap();
+        let mut writer = Writer::open(&path, 6, 1).unwrap();
+        writer.write_line(b"new\n").unwrap();
+        assert_eq!(read(&path), "new\n");
+        assert_eq!(read(&rotated_path(&path, 1)), "old\n");
+    }
+
+    #[test]
+    fn rotation_without_keep_truncates() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut writer = Writer::open(&path, 4, 0).unwrap();
+        for line in ["a\n", "b\n", "c\n"] {
+            writer.write_line(line.as_bytes()).unwrap();
+        }
+        assert_eq!(read(&path), "c\n");
+        assert!(!rotated_path(&path, 1).exists());
+    }
+
+    #[test]
+    fn no_rotation_without_max_size() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("audit.log");
+        let mut writer = Writer::open(&path, 0, 2).unwrap();
+        for _ in 0..100 {
+            writer.write_line(b"line\n").unwrap();
+        }
+        assert_eq!(read(&path).len(), 500);
+        assert!(!rotated_path(&path, 1).exists());
+    }
+}
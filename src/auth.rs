@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::error;
+
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+// ordered so that a higher role includes every permission of the lower ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    ReadOnly,
+    Operator,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::ReadOnly => "read-only",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" | "readonly" => Ok(Role::ReadOnly),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role: {}", s)),
+        }
+    }
+}
+
+// who made a request, as recorded in the audit log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Identity {
+    fn anonymous() -> Self {
+        Identity {
+            name: "anonymous".to_string(),
+            role: Role::Admin,
+        }
+    }
+}
+
+pub type Tokens = HashMap<String, Identity>;
+
+// token file: one "<role> <token> [name]" entry per line, '#' starts a comment;
+// without a name the token is identified by its role and line number
+pub fn parse_tokens(text: &str) -> Result<Tokens, String> {
+    let mut tokens = Tokens::new();
+    for (no, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(role), Some(token), name, None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format!("line {}: expected \"<role> <token> [name]\"", no + 1));
+        };
+        let role: Role = role
+            .parse()
+            .map_err(|e| format!("line {}: {}", no + 1, e))?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => format!("{}-{}", role, no + 1),
+        };
+        // a token given twice would silently get the role of the later line
+        if tokens.insert(token.to_string(), Identity { name, role }).is_some() {
+            return Err(format!("line {}: duplicate token", no + 1));
+        }
+    }
+    Ok(tokens)
+}
+
+pub fn load_tokens(path: &str) -> io::Result<Tokens> {
+    let text = std::fs::read_to_string(path)?;
+    parse_tokens(&text)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+
+// Resolves the bearer token to an identity and attaches it to the request.
+// The RPC itself is not known here, so handlers check the role with `require`.
+#[derive(Debug, Clone, Default)]
+pub struct AuthInterceptor {
+    tokens: Option<Arc<Tokens>>,
+}
+
+impl AuthInterceptor {
+    // None disables authorization and grants every request the admin role
+    pub fn new(tokens: Option<Tokens>) -> Self {
+        AuthInterceptor {
+            tokens: tokens.map(Arc::new),
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let identity = match &self.tokens {
+            None => Identity::anonymous(),
+            Some(tokens) => {
+                let token = request
+                    .metadata()
+                    .get(AUTHORIZATION_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix(BEARER_PREFIX))
+                    .ok_or_else(|| error::unauthenticated_status("missing bearer token"))?;
+                tokens
+                    .get(token.trim())
+                    .cloned()
+                    .ok_or_else(|| error::unauthenticated_status("unknown bearer token"))?
+            }
+        };
+        request.extensions_mut().insert(identity);
+        Ok(request)
+    }
+}
+
+// requests that did not pass the interceptor come from an in-process server
+// without authorization, so they are treated like the disabled case
+pub fn identity<T>(request: &Request<T>) -> Identity {
+    request
+        .extensions()
+        .get::<Identity>()
+        .cloned()
+        .unwrap_or_else(Identity::anonymous)
+}
+
+pub fn role<T>(request: &Request<T>) -> Role {
+    request
+        .extensions()
+        .get::<Identity>()
+        .map_or(Role::Admin, |identity| identity.role)
+}
+
+pub fn check(role: Role, required: Role) -> Result<(), Status> {
+    if role >= required {
+        Ok(())
+    } else {
+        Err(error::role_status(&required.to_string(), &role.to_string()))
+    }
+}
+
+pub fn require<T>(request: &Request<T>, required: Role) -> Result<(), Status> {
+    check(role(request), required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_token_lines() {
+        let text = "# role token name\n\nadmin a-token alice\n  operator o-token # ci\n\
+                    readonly r-token\n";
+        let tokens = parse_tokens(text).unwrap();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(
+            tokens["a-token"],
+            Identity { name: "alice".to_string(), role: Role::Admin }
+        );
+        assert_eq!(
+            tokens["o-token"],
+            Identity { name: "operator-4".to_string(), role: Role::Operator }
+        );
+        assert_eq!(tokens["r-token"].role, Role::ReadOnly);
+        assert!(parse_tokens("# only comments\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reject_malformed_lines() {
+        let err = parse_tokens("admin\n").unwrap_err();
+        assert!(err.starts_with("line 1: expected"), "{}", err);
+        let err = parse_tokens("admin a-token\nadmin b-token bob extra\n").unwrap_err();
+        assert!(err.starts_with("line 2: expected"), "{}", err);
+    }
+
+    #[test]
+    fn reject_duplicate_tokens() {
+        let err = parse_tokens("read-only token\nadmin token\n").unwrap_err();
+        assert_eq!(err, "line 2: duplicate token");
+    }
+
+    #[test]
+    fn reject_unknown_roles() {
+        let err = parse_tokens("admin a-token\nroot r-token\n").unwrap_err();
+        assert_eq!(err, "line 2: unknown role: root");
+        assert!(parse_tokens("Admin a-token\n").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/jelly-fpga-server/config.toml";
+pub const DEFAULT_PORT: u16 = 8051;
+pub const DEFAULT_FIRMWARE_DIR: &str = "/lib/firmware";
+pub const DEFAULT_FIRMWARE_STAGING_DIR: &str = "jelly-fpga-server";
+pub const DEFAULT_AUDIT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_AUDIT_KEEP: u32 = 5;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub verbose: i32,
+    pub log: LogConfig,
+    pub server: ServerConfig,
+    pub firmware: FirmwareConfig,
+    pub platform: PlatformConfig,
+    pub security: SecurityConfig,
+    pub access: AccessConfig,
+    pub features: FeatureConfig,
+    pub metrics: MetricsConfig,
+    pub audit: AuditConfig,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+    Journald,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// default level; derived from `verbose` when unset
+    pub level: Option<String>,
+    pub format: LogFormat,
+    /// per-subsystem levels, e.g. firmware = "debug"
+    pub filters: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: Option<String>,
+    pub external: bool,
+    pub port: u16,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: None,
+            external: false,
+            port: DEFAULT_PORT,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn address(&self) -> String {
+        if let Some(bind_ip) = &self.bind {
+            format!("{}:{}", bind_ip, self.port)
+        } else if self.external {
+            format!("0.0.0.0:{}", self.port)
+        } else {
+            format!("127.0.0.1:{}", self.port)
+        }
+    }
+}
+
+/// How files are made visible to the kernel when `dir` is not its firmware
+/// search path; unused otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum FirmwareStaging {
+    /// copy each file into `staging_dir` below /lib/firmware before loading it
+    #[default]
+    Copy,
+    /// point the firmware_class.path module parameter at `dir` on startup
+    ClassPath,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirmwareConfig {
+    pub dir: String,
+    pub staging: FirmwareStaging,
+    /// relative to /lib/firmware
+    pub staging_dir: String,
+}
+
+impl Default for FirmwareConfig {
+    fn default() -> Self {
+        FirmwareConfig {
+            dir: DEFAULT_FIRMWARE_DIR.to_string(),
+            staging: FirmwareStaging::default(),
+            staging_dir: DEFAULT_FIRMWARE_STAGING_DIR.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// the board, through jelly-fpgautil
+    #[default]
+    Fpgautil,
+    /// FPGA manager, overlays and remoteprocs simulated in `sim_dir`
+    Sim,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlatformConfig {
+    pub backend: Backend,
+    /// directory of the sim backend, a fixed directory below the system
+    /// temporary directory when unset
+    pub sim_dir: Option<String>,
+    /// file the fpgautil backend keeps the slots of `Load` in,
+    /// /run/jelly-fpga-server/slots.json when unset
+    pub slots_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    pub allow_sudo: bool,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_client_ca: Option<String>,
+    pub token_file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MmapRegion {
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+// an unset list allows everything, an empty list allows nothing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessConfig {
+    pub mmap: Option<Vec<MmapRegion>>,
+    pub uio: Option<Vec<String>>,
+    pub udmabuf: Option<Vec<String>>,
+}
+
+impl AccessConfig {
+    pub fn allows_mmap(&self, path: &str, offset: u64, size: u64) -> bool {
+        let Some(regions) = &self.mmap else {
+            return true;
+        };
+        let Some(end) = offset.checked_add(size) else {
+            return false;
+        };
+        // compared after resolving, so `/dev//mem`, `/dev/./mem` or a symlink name the same file
+        let Ok(path) = std::fs::canonicalize(path) else {
+            return false;
+        };
+        regions.iter().any(|region| {
+            std::fs::canonicalize(&region.path).is_ok_and(|region_path| region_path == path)
+                && offset >= region.offset
+                && end <= region.offset.saturating_add(region.size)
+        })
+    }
+
+    pub fn allows_uio(&self, name: &str) -> bool {
+        self.uio.as_ref().is_none_or(|names| names.iter().any(|n| n == name))
+    }
+
+    pub fn allows_udmabuf(&self, name: &str) -> bool {
+        self.udmabuf
+            .as_ref()
+            .is_none_or(|names| names.iter().any(|n| n == name))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    /// UploadFirmware, RemoveFirmware, BitstreamToBin
+    pub firmware: bool,
+    /// Load, Unload, accel registration, LoadBitstream, LoadDtbo
+    pub fpga: bool,
+    /// remoteproc load/start/stop
+    pub remoteproc: bool,
+    /// opening mmap/uio/udmabuf/sim accessors
+    pub accessor: bool,
+}
+
+impl Default for FeatureConfig {
+    fn default() -> Self {
+        FeatureConfig {
+            firmware: true,
+            fpga: true,
+            remoteproc: true,
+            accessor: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// address of the Prometheus HTTP endpoint, disabled when unset
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// JSON lines file, the audit log is disabled when unset
+    pub path: Option<String>,
+    /// size in bytes at which the file is rotated, 0 never rotates
+    pub max_size: u64,
+    /// number of rotated files kept as <path>.1 .. <path>.<keep>
+    pub keep: u32,
+    /// also record register and memory writes
+    pub writes: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            path: None,
+            max_size: DEFAULT_AUDIT_MAX_SIZE,
+            keep: DEFAULT_AUDIT_KEEP,
+            writes: false,
+        }
+    }
+}
+
+impl Config {
+    // a missing file is only an error when it was named explicitly
+    pub fn load(path: &str, required: bool) -> Result<Config, Box<dyn Error>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Config::default());
+            }
+            Err(e) => return Err(format!("failed to read {}: {}", path, e).into()),
+        };
+        toml::from_str(&text).map_err(|e| format!("failed to parse {}: {}", path, e).into())
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let security = &self.security;
+        if security.tls_cert.is_some() != security.tls_key.is_some() {
+            return Err("tls_cert and tls_key must be given together".into());
+        }
+        if security.tls_client_ca.is_some() && security.tls_cert.is_none() {
+            return Err("tls_client_ca requires tls_cert and tls_key".into());
+        }
+        if crate::firmware::path::check_name(&self.firmware.staging_dir).is_err() {
+            return Err("staging_dir must be a relative path without '..'".into());
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn tls(cert: bool, key: bool, client_ca: bool) -> Config {
+        let path = |set: bool, name: &str| set.then(|| format!("/etc/jelly/{}", name));
+        let mut config = Config::default();
+        config.security.tls_cert = path(cert, "server.crt");
+        config.security.tls_key = path(key, "server.key");
+        config.security.tls_client_ca = path(client_ca, "ca.crt");
+        config
+    }
+
+    #[test]
+    fn load_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let path = path.to_str().unwrap();
+        assert_eq!(Config::load(path, false).unwrap().server.port, DEFAULT_PORT);
+        assert!(Config::load(path, true).is_err());
+
+        std::fs::write(path, "[server]\nport = 9000\n[audit]\nkeep = 2\n").unwrap();
+        let config = Config::load(path, true).unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.audit.keep, 2);
+        assert_eq!(config.audit.max_size, DEFAULT_AUDIT_MAX_SIZE);
+
+        std::fs::write(path, "[server]\nprot = 9000\n").unwrap();
+        let err = Config::load(path, true).unwrap_err().to_string();
+        assert!(err.contains("unknown field"), "{}", err);
+    }
+
+    #[test]
+    fn validate_tls() {
+        assert!(tls(false, false, false).validate().is_ok());
+        assert!(tls(true, true, false).validate().is_ok());
+        assert!(tls(true, true, true).validate().is_ok());
+        let err = tls(true, false, false).validate().unwrap_err().to_string();
+        assert_eq!(err, "tls_cert and tls_key must be given together");
+        assert!(tls(false, true, false).validate().is_err());
+        let err = tls(false, false, true).validate().unwrap_err().to_string();
+        assert_eq!(err, "tls_client_ca requires tls_cert and tls_key");
+    }
+
+    #[test]
+    fn validate_staging_dir() {
+        let mut config = Config::default();
+        config.firmware.staging_dir = "vendor/jelly".to_string();
+        assert!(config.validate().is_ok());
+        for dir in ["", "/lib/firmware", "../firmware", "jelly/../../etc"] {
+            config.firmware.staging_dir = dir.to_string();
+            let err = config.validate().unwrap_err().to_string();
+            assert!(err.starts_with("staging_dir"), "{}: {}", dir, err);
+        }
+    }
+
+    #[test]
+    fn mmap_paths_are_resolved() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("mem"), [0; 16]).unwrap();
+        std::fs::write(dir.path().join("other"), [0; 16]).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("mem"), dir.path().join("link")).unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let access = AccessConfig {
+            mmap: Some(vec![MmapRegion { path: format!("{}/./mem", dir), offset: 0, size: 16 }]),
+            ..Default::default()
+        };
+
+        assert!(access.allows_mmap(&format!("{}/mem", dir), 0, 16));
+        assert!(access.allows_mmap(&format!("{}//mem", dir), 0, 16));
+        assert!(access.allows_mmap(&format!("{}/./mem", dir), 0, 16));
+        assert!(access.allows_mmap(&format!("{}/link", dir), 0, 16));
+        assert!(!access.allows_mmap(&format!("{}/link", dir), 8, 16));
+        assert!(!access.allows_mmap(&format!("{}//other", dir), 0, 16));
+        assert!(!access.allows_mmap(&format!("{}/missing", dir), 0, 16));
+    }
+}
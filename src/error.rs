@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+use crate::accessor::AccessorError;
+use crate::firmware::path::PathError;
+
+const ERROR_DOMAIN: &str = "jelly-fpga-server";
+
+fn io_code(kind: io::ErrorKind) -> Option<Code> {
+    match kind {
+        io::ErrorKind::NotFound => Some(Code::NotFound),
+        io::ErrorKind::PermissionDenied => Some(Code::PermissionDenied),
+        io::ErrorKind::AlreadyExists => Some(Code::AlreadyExists),
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => Some(Code::InvalidArgument),
+        io::ErrorKind::TimedOut => Some(Code::DeadlineExceeded),
+        _ => None,
+    }
+}
+
+// fpgautil and uidmng mostly report failures as plain strings,
+// so fall back to the message text when there is no io::Error to inspect.
+fn message_code(message: &str) -> Option<Code> {
+    let message = message.to_lowercase();
+    if message.contains("permission denied")
+        || message.contains("not permitted")
+        || message.contains("sudo")
+    {
+        Some(Code::PermissionDenied)
+    } else if message.contains("no such file") || message.contains("not found") {
+        Some(Code::NotFound)
+    } else {
+        None
+    }
+}
+
+fn classify(kind: Option<io::ErrorKind>, message: &str, fallback: Code) -> Code {
+    kind.and_then(io_code)
+        .or_else(|| message_code(message))
+        .unwrap_or(fallback)
+}
+
+fn error_info(reason: &str, metadata: &[(&str, String)]) -> ErrorDetails {
+    let metadata: HashMap<String, String> = metadata
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect();
+    ErrorDetails::with_error_info(reason, ERROR_DOMAIN, metadata)
+}
+
+impl From<AccessorError> for Status {
+    fn from(err: AccessorError) -> Self {
+        let message = err.to_string();
+        match err {
+            AccessorError::InvalidId(id) => {
+                let mut details = error_info("ACCESSOR_NOT_FOUND", &[("id", id.to_string())]);
+                details.set_resource_info("accessor", id.to_string(), "", "accessor is not open");
+                Status::with_error_details(Code::NotFound, message, details)
+            }
+            AccessorError::InvalidSize(size) => {
+                let mut details =
+                    error_info("INVALID_ACCESS_SIZE", &[("size", size.to_string())]);
+                details.add_bad_request_violation("size", "access size must be 0, 1, 2, 4 or 8");
+                Status::with_error_details(Code::InvalidArgument, message, details)
+            }
+            AccessorError::OutOfRange {
+                id,
+                offset,
+                len,
+                size,
+            } => {
+                let mut details = error_info(
+                    "ACCESS_OUT_OF_RANGE",
+                    &[
+                        ("id", id.to_string()),
+                        ("offset", offset.to_string()),
+                        ("len", len.to_string()),
+                        ("size", size.to_string()),
+                    ],
+                );
+                details.add_bad_request_violation("offset", "access must lie within the accessor");
+                Status::with_error_details(Code::OutOfRange, message, details)
+            }
+            AccessorError::Misaligned { id, offset, align } => {
+                let mut details = error_info(
+                    "MISALIGNED_ACCESS",
+                    &[
+                        ("id", id.to_string()),
+                        ("offset", offset.to_string()),
+                        ("align", align.to_string()),
+                    ],
+                );
+                details.add_bad_request_violation(
+                    "offset",
+                    format!("offset must be {}-byte aligned", align),
+                );
+                Status::with_error_details(Code::InvalidArgument, message, details)
+            }
+            AccessorError::RegOverflow { id, reg, unit } => {
+                let mut details = error_info(
+                    "REGISTER_OVERFLOW",
+                    &[
+                        ("id", id.to_string()),
+                        ("reg", reg.to_string()),
+                        ("unit", unit.to_string()),
+                    ],
+                );
+                details.add_bad_request_violation("reg", "register offset overflows");
+                Status::with_error_details(Code::OutOfRange, message, details)
+            }
+            AccessorError::NotUio(id) => {
+                let mut details = error_info("NOT_UIO", &[("id", id.to_string())]);
+                details.add_precondition_failure_violation(
+                    "UIO",
+                    id.to_string(),
+                    "accessor must be opened with OpenUio",
+                );
+                Status::with_error_details(Code::FailedPrecondition, message, details)
+            }
+            AccessorError::Open { target, kind, .. } => {
+                let code = classify(kind, &message, Code::Internal);
+                let details = error_info("OPEN_FAILED", &[("target", target)]);
+                Status::with_error_details(code, message, details)
+            }
+        }
+    }
+}
+
+fn operation_status(operation: &str, err: &(dyn Error + 'static), fallback: Code) -> Status {
+    let message = err.to_string();
+    let kind = err.downcast_ref::<io::Error>().map(|e| e.kind());
+    let code = classify(kind, &message, fallback);
+    let details = error_info(
+        "OPERATION_FAILED",
+        &[("operation", operation.to_string())],
+    );
+    Status::with_error_details(code, format!("{} failed: {}", operation, message), details)
+}
+
+// failure of fpgautil/uidmng operations on the board
+pub fn platform_status(operation: &str, err: Box<dyn Error>) -> Status {
+    operation_status(operation, err.as_ref(), Code::Internal)
+}
+
+// failure of conversions (dtc, bootgen) where the input is the usual culprit
+pub fn conversion_status(operation: &str, err: Box<dyn Error>) -> Status {
+    operation_status(operation, err.as_ref(), Code::InvalidArgument)
+}
+
+pub fn precondition_status(violation_type: &str, subject: &str, description: &str) -> Status {
+    let mut details = error_info(violation_type, &[("subject", subject.to_string())]);
+    details.add_precondition_failure_violation(violation_type, subject, description);
+    Status::with_error_details(
+        Code::FailedPrecondition,
+        format!("{}: {}", subject, description),
+        details,
+    )
+}
+
+pub fn bad_request_status(field: &str, description: &str) -> Status {
+    let mut details = error_info("INVALID_REQUEST", &[("field", field.to_string())]);
+    details.add_bad_request_violation(field, description);
+    Status::with_error_details(
+        Code::InvalidArgument,
+        format!("{}: {}", field, description),
+        details,
+    )
+}
+
+// names the client sent for files in the firmware directory
+pub fn firmware_name_status(field: &str, err: PathError) -> Status {
+    let message = err.to_string();
+    if let PathError::Io(_, e) = &err {
+        let code = classify(Some(e.kind()), &message, Code::Internal);
+        let details = error_info("OPERATION_FAILED", &[("field", field.to_string())]);
+        return Status::with_error_details(code, format!("{}: {}", field, message), details);
+    }
+    let mut details = error_info("INVALID_FIRMWARE_NAME", &[("field", field.to_string())]);
+    details.add_bad_request_violation(field, &message);
+    Status::with_error_details(
+        Code::InvalidArgument,
+        format!("{}: {}", field, message),
+        details,
+    )
+}
+
+pub fn checksum_status(algorithm: &str, expected: &str, actual: &str) -> Status {
+    let details = error_info(
+        "CHECKSUM_MISMATCH",
+        &[
+            ("algorithm", algorithm.to_string()),
+            ("expected", expected.to_string()),
+            ("actual", actual.to_string()),
+        ],
+    );
+    Status::with_error_details(
+        Code::DataLoss,
+        format!("{} mismatch: expected {} actual {}", algorithm, expected, actual),
+        details,
+    )
+}
+
+pub fn file_range_status(name: &str, offset: u64, length: u64, size: u64) -> Status {
+    let mut details = error_info(
+        "FILE_OUT_OF_RANGE",
+        &[
+            ("name", name.to_string()),
+            ("offset", offset.to_string()),
+            ("length", length.to_string()),
+            ("size", size.to_string()),
+        ],
+    );
+    details.add_bad_request_violation("offset", "range must lie within the file");
+    Status::with_error_details(
+        Code::OutOfRange,
+        format!(
+            "{}: offset {} length {} exceeds file size {}",
+            name, offset, length, size
+        ),
+        details,
+    )
+}
+
+pub fn session_status(session: u64) -> Status {
+    let mut details = error_info("SESSION_NOT_FOUND", &[("session", session.to_string())]);
+    details.set_resource_info(
+        "session",
+        session.to_string(),
+        "",
+        "session is closed or expired",
+    );
+    Status::with_error_details(
+        Code::NotFound,
+        format!("session {} is closed or expired", session),
+        details,
+    )
+}
+
+pub fn unauthenticated_status(message: &str) -> Status {
+    let details = error_info("INVALID_TOKEN", &[]);
+    Status::with_error_details(Code::Unauthenticated, message, details)
+}
+
+pub fn role_status(required: &str, actual: &str) -> Status {
+    let details = error_info(
+        "INSUFFICIENT_ROLE",
+        &[
+            ("required", required.to_string()),
+            ("role", actual.to_string()),
+        ],
+    );
+    Status::with_error_details(
+        Code::PermissionDenied,
+        format!("{} role required, token has {}", required, actual),
+        details,
+    )
+}
+
+pub fn not_allowed_status(kind: &str, target: &str) -> Status {
+    let details = error_info(
+        "ACCESS_NOT_ALLOWED",
+        &[("kind", kind.to_string()), ("target", target.to_string())],
+    );
+    Status::with_error_details(
+        Code::PermissionDenied,
+        format!("{} access to {} is not allowed by the server configuration", kind, target),
+        details,
+    )
+}
+
+pub fn feature_status(feature: &str) -> Status {
+    precondition_status(
+        "FEATURE_DISABLED",
+        feature,
+        "disabled by the server configuration",
+    )
+}
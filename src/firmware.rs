@@ -0,0 +1,398 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::UNIX_EPOCH;
+
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::jelly_fpga_control::{FirmwareInfo, FirmwareType};
+use crate::logging;
+use crate::platform::{PlatformBackend, PlatformResult};
+
+pub mod path;
+use path::FirmwareRoot;
+
+pub mod staging;
+
+// header of a Xilinx .bit file, followed by the design name and date fields
+const BIT_HEADER: &[u8] = &[
+    0x00, 0x09, 0x0f, 0xf0, 0x0f, 0xf0, 0x0f, 0xf0, 0x0f, 0xf0, 0x00, 0x00, 0x01,
+];
+// configuration sync word, byte swapped in .bin files made for the FPGA manager
+const SYNC_WORD: &[u8] = &[0xaa, 0x99, 0x55, 0x66];
+const SYNC_WORD_SWAPPED: &[u8] = &[0x66, 0x55, 0x99, 0xaa];
+const FDT_MAGIC: &[u8] = &[0xd0, 0x0d, 0xfe, 0xed];
+const ELF_MAGIC: &[u8] = &[0x7f, b'E', b'L', b'F'];
+const SHELL_JSON: &str = "shell.json";
+// a .bin starts with padding words, the sync word follows within this range
+const DETECT_LEN: usize = 256;
+const READ_BUFFER: usize = 64 * 1024;
+
+static UPLOAD_COUNTER: AtomicU64 = AtomicU64::new(0);
+const UPLOAD_SUFFIX: &str = ".upload-";
+
+pub fn detect_type(name: &str, head: &[u8]) -> FirmwareType {
+    let contains = |needle: &[u8]| head.windows(needle.len()).any(|w| w == needle);
+    if head.starts_with(BIT_HEADER) {
+        FirmwareType::Bit
+    } else if head.starts_with(FDT_MAGIC) {
+        FirmwareType::Dtbo
+    } else if head.starts_with(ELF_MAGIC) {
+        FirmwareType::Elf
+    } else if contains(SYNC_WORD) || contains(SYNC_WORD_SWAPPED) {
+        FirmwareType::Bin
+    } else if name.rsplit('/').next() == Some(SHELL_JSON) {
+        FirmwareType::ShellJson
+    } else {
+        FirmwareType::Unknown
+    }
+}
+
+/// Type of a file from its first bytes, as `ListFirmware` reports it.
+pub fn detect_file(path: &Path) -> io::Result<FirmwareType> {
+    let mut head = Vec::with_capacity(DETECT_LEN);
+    File::open(path)?
+        .take(DETECT_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(detect_type(&path.to_string_lossy(), &head))
+}
+
+/// Longest pattern `ListFirmware` accepts.
+pub const MAX_PATTERN_LEN: usize = 256;
+
+#[derive(Debug)]
+enum Token {
+    Star,
+    Any,
+    Class { ranges: Vec<(char, char)>, negate: bool },
+    Literal(char),
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Star | Token::Any => true,
+            Token::Class { ranges, negate } => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negate
+            }
+            Token::Literal(p) => *p == c,
+        }
+    }
+}
+
+// '[' without a closing ']' in the same segment is taken literally
+fn parse_class(pattern: &[char]) -> Option<(Token, usize)> {
+    let negate = matches!(pattern.first(), Some('!' | '^'));
+    let mut i = usize::from(negate);
+    let mut ranges = Vec::new();
+    let mut first = true;
+    while i < pattern.len() {
+        if pattern[i] == ']' && !first {
+            return Some((Token::Class { ranges, negate }, i + 1));
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            ranges.push((pattern[i], pattern[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((pattern[i], pattern[i]));
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+fn tokenize(segment: &str) -> Vec<Token> {
+    let pattern: Vec<char> = segment.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < pattern.len() {
+        let token = match pattern[i] {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '[' => match parse_class(&pattern[i + 1..]) {
+                Some((class, len)) => {
+                    i += len;
+                    class
+                }
+                None => Token::Literal('['),
+            },
+            c => Token::Literal(c),
+        };
+        i += 1;
+        tokens.push(token);
+    }
+    tokens
+}
+
+// Matches one '/' free segment. A mismatch only ever backtracks to the last
+// '*', which is enough because a later '*' can absorb anything an earlier one
+// could, so the cost stays at pattern length times name length.
+fn match_segment(tokens: &[Token], name: &[char]) -> bool {
+    let (mut t, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match tokens.get(t) {
+            Some(Token::Star) => {
+                star = Some((t, n));
+                t += 1;
+            }
+            Some(token) if token.matches(name[n]) => {
+                t += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_t, star_n)) => {
+                    t = star_t + 1;
+                    n = star_n + 1;
+                    star = Some((star_t, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    tokens[t..].iter().all(|token| matches!(token, Token::Star))
+}
+
+/// Shell-style match where `*`, `?` and `[...]` do not match a '/'.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut patterns = pattern.split('/');
+    let mut names = name.split('/');
+    loop {
+        match (patterns.next(), names.next()) {
+            (None, None) => return true,
+            (Some(pattern), Some(name)) => {
+                let name: Vec<char> = name.chars().collect();
+                if !match_segment(&tokenize(pattern), &name) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+    if pattern.contains('/') {
+        glob_match(pattern, name)
+    } else {
+        glob_match(pattern, name.rsplit('/').next().unwrap_or(name))
+    }
+}
+
+pub fn sha256_hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// reads the file once for both the digest and the type detection
+fn describe(path: &Path, name: String) -> io::Result<FirmwareInfo> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let mtime_ms = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(DETECT_LEN);
+    let mut buf = vec![0u8; READ_BUFFER];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        if head.len() < DETECT_LEN {
+            let take = n.min(DETECT_LEN - head.len());
+            head.extend_from_slice(&buf[..take]);
+        }
+        hasher.update(&buf[..n]);
+    }
+    let sha256 = sha256_hex(hasher);
+
+    let r#type = detect_type(&name, &head);
+    Ok(FirmwareInfo {
+        name,
+        size: metadata.len(),
+        mtime_ms,
+        sha256,
+        r#type: r#type as i32,
+    })
+}
+
+/// Regular files below `root`, sorted by name. Symlinked directories are not
+/// followed, symlinks pointing outside the directory and unreadable
+/// subdirectories and files are skipped.
+pub fn list(root: &FirmwareRoot, pattern: &str, recursive: bool) -> io::Result<Vec<FirmwareInfo>> {
+    let dir = root.as_path();
+    let mut files = Vec::new();
+    let mut dirs = vec![String::new()];
+    while let Some(prefix) = dirs.pop() {
+        let entries = match std::fs::read_dir(dir.join(&prefix)) {
+            Ok(entries) => entries,
+            Err(e) if !prefix.is_empty() => {
+                warn!(target: logging::FIRMWARE, dir = prefix, error = %e, "skip directory");
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            // uploads in progress
+            if file_name.starts_with('.') && file_name.contains(UPLOAD_SUFFIX) {
+                continue;
+            }
+            let name = format!("{}{}", prefix, file_name);
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if recursive {
+                    dirs.push(format!("{}/", name));
+                }
+                continue;
+            }
+            let path = entry.path();
+            if !path.is_file() || !matches_pattern(pattern, &name) {
+                continue;
+            }
+            if file_type.is_symlink() && root.resolve(&name).is_err() {
+                continue;
+            }
+            match describe(&path, name) {
+                Ok(info) => files.push(info),
+                Err(e) => warn!(
+                    target: logging::FIRMWARE,
+                    path = %path.display(),
+                    error = %e,
+                    "skip file"
+                ),
+            }
+        }
+    }
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(files)
+}
+
+/// Temporary file next to the destination that an upload is written to. A
+/// failed upload is removed with `discard`, drop only removes what the server
+/// user may delete without raising privileges.
+#[derive(Debug)]
+pub struct PartialUpload {
+    temp: String,
+    dest: String,
+    committed: bool,
+}
+
+impl PartialUpload {
+    pub fn new(dest: &str) -> Self {
+        let path = Path::new(dest);
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(
+            ".{}{}{}-{}",
+            file_name,
+            UPLOAD_SUFFIX,
+            std::process::id(),
+            UPLOAD_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        PartialUpload {
+            temp: temp.to_string_lossy().into_owned(),
+            dest: dest.to_string(),
+            committed: false,
+        }
+    }
+
+    pub fn temp_path(&self) -> &str {
+        &self.temp
+    }
+
+    // rename is atomic within the directory, readers never see a partial file
+    pub fn commit(mut self, platform: &dyn PlatformBackend) -> PlatformResult<()> {
+        if let Err(e) = platform.rename_file(Path::new(&self.temp), Path::new(&self.dest)) {
+            self.discard(platform);
+            return Err(e);
+        }
+        self.committed = true;
+        Ok(())
+    }
+
+    pub fn discard(mut self, platform: &dyn PlatformBackend) {
+        self.committed = true;
+        let temp = Path::new(&self.temp);
+        if temp.symlink_metadata().is_ok()
+            && let Err(e) = platform.remove_file(temp)
+        {
+            warn!(target: logging::FIRMWARE, path = self.temp, error = %e, "partial upload left");
+        }
+    }
+}
+
+impl Drop for PartialUpload {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let temp = PathBuf::from(&self.temp);
+        if temp.exists()
+            && let Err(e) = std::fs::remove_file(&temp)
+        {
+            warn!(target: logging::FIRMWARE, path = self.temp, error = %e, "partial upload left");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{glob_match, matches_pattern};
+
+    #[test]
+    fn star_and_question_mark() {
+        assert!(glob_match("*.bin", "design.bin"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("d*g*.b?n", "design.bin"));
+        assert!(glob_match("*a*", "banana"));
+        assert!(!glob_match("*.bin", "design.bit"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("design.bin?", "design.bin"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_match("fpga[0-3].bin", "fpga2.bin"));
+        assert!(!glob_match("fpga[0-3].bin", "fpga7.bin"));
+        assert!(glob_match("fpga[!0-3].bin", "fpga7.bin"));
+        assert!(glob_match("fpga[^0-3].bin", "fpga7.bin"));
+        assert!(!glob_match("fpga[!0-3].bin", "fpga1.bin"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[a-", "[a-"));
+    }
+
+    #[test]
+    fn separators() {
+        assert!(glob_match("xilinx/*/shell.json", "xilinx/accel/shell.json"));
+        assert!(!glob_match("xilinx/*", "xilinx/accel/shell.json"));
+        assert!(!glob_match("*.json", "accel/shell.json"));
+        assert!(!glob_match("a?b", "a/b"));
+        assert!(!glob_match("a[/]b", "a/b"));
+        assert!(matches_pattern("*.json", "xilinx/accel/shell.json"));
+        assert!(!matches_pattern("accel/*.json", "xilinx/accel/shell.json"));
+    }
+
+    #[test]
+    fn worst_case_is_not_exponential() {
+        let pattern = format!("{}b", "*a".repeat(100));
+        let name = "a".repeat(200);
+        let start = Instant::now();
+        assert!(!glob_match(&pattern, &name));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug)]
+pub enum PathError {
+    Empty,
+    Absolute(String),
+    ParentDir(String),
+    NotComponent(String),
+    Escape(String),
+    Io(String, io::Error),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "name is empty"),
+            PathError::Absolute(name) => write!(f, "{}: absolute paths are not allowed", name),
+            PathError::ParentDir(name) => write!(f, "{}: '..' is not allowed", name),
+            PathError::NotComponent(name) => write!(f, "{}: must be a single file name", name),
+            PathError::Escape(name) => {
+                write!(f, "{}: resolves outside the firmware directory", name)
+            }
+            PathError::Io(name, e) => write!(f, "{}: {}", name, e),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+// checks that need no file system access
+pub fn check_name(name: &str) -> Result<(), PathError> {
+    let mut normal = false;
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(_) => normal = true,
+            Component::CurDir => {}
+            Component::ParentDir => return Err(PathError::ParentDir(name.to_string())),
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(PathError::Absolute(name.to_string()));
+            }
+        }
+    }
+    if normal {
+        Ok(())
+    } else {
+        Err(PathError::Empty)
+    }
+}
+
+/// Names that become a single directory entry, such as accelerator names.
+pub fn check_component(name: &str) -> Result<(), PathError> {
+    check_name(name)?;
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(PathError::NotComponent(name.to_string())),
+    }
+}
+
+/// The firmware directory, resolving names sent by clients below it.
+#[derive(Debug, Clone, Default)]
+pub struct FirmwareRoot {
+    root: PathBuf,
+}
+
+impl FirmwareRoot {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FirmwareRoot { root: root.into() }
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn canonical(&self) -> Result<PathBuf, PathError> {
+        self.root
+            .canonicalize()
+            .map_err(|e| PathError::Io(self.root.display().to_string(), e))
+    }
+
+    /// Canonical path of `name` inside the firmware directory. The file itself
+    /// may not exist yet, but every existing part of the path has its symlinks
+    /// resolved and must stay inside the directory.
+    pub fn resolve(&self, name: &str) -> Result<PathBuf, PathError> {
+        check_name(name)?;
+        self.resolve_below(Path::new(name), name)
+    }
+
+    /// Like `resolve`, but a symlink in the last component is not followed, for
+    /// operations on the directory entry itself such as removal.
+    pub fn resolve_entry(&self, name: &str) -> Result<PathBuf, PathError> {
+        check_name(name)?;
+        let mut parts: Vec<_> = Path::new(name)
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let Some(last) = parts.pop() else {
+            return Err(PathError::Empty);
+        };
+        let parent: PathBuf = parts.iter().collect();
+        Ok(self.resolve_below(&parent, name)?.join(last))
+    }
+
+    // `name` is only used in errors
+    fn resolve_below(&self, relative: &Path, name: &str) -> Result<PathBuf, PathError> {
+        let root = self.canonical()?;
+        let mut existing = root.join(relative);
+        let mut missing = Vec::new();
+        let resolved = loop {
+            match existing.canonicalize() {
+                Ok(path) => break path,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    // a dangling symlink would be followed once the file is created
+                    if existing.symlink_metadata().is_ok() {
+                        return Err(PathError::Escape(name.to_string()));
+                    }
+                    match existing.file_name() {
+                        Some(file_name) => missing.push(file_name.to_owned()),
+                        None => return Err(PathError::Io(name.to_string(), e)),
+                    }
+                    existing.pop();
+                }
+                Err(e) => return Err(PathError::Io(name.to_string(), e)),
+            }
+        };
+        if !resolved.starts_with(&root) {
+            return Err(PathError::Escape(name.to_string()));
+        }
+        Ok(missing
+            .iter()
+            .rev()
+            .fold(resolved, |path, part| path.join(part)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn setup() -> (tempfile::TempDir, FirmwareRoot) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("firmware");
+        std::fs::create_dir_all(root.join("xilinx/k26")).unwrap();
+        std::fs::write(root.join("design.bit"), b"bit").unwrap();
+        std::fs::write(root.join("xilinx/k26/k26.bin"), b"bin").unwrap();
+        std::fs::write(dir.path().join("secret"), b"secret").unwrap();
+        (dir, FirmwareRoot::new(root))
+    }
+
+    fn canonical_root(root: &FirmwareRoot) -> PathBuf {
+        root.as_path().canonicalize().unwrap()
+    }
+
+    #[test]
+    fn resolves_existing_files() {
+        let (_dir, root) = setup();
+        let base = canonical_root(&root);
+        assert_eq!(root.resolve("design.bit").unwrap(), base.join("design.bit"));
+        assert_eq!(
+            root.resolve("./xilinx/k26/k26.bin").unwrap(),
+            base.join("xilinx/k26/k26.bin")
+        );
+    }
+
+    #[test]
+    fn resolves_files_that_do_not_exist_yet() {
+        let (_dir, root) = setup();
+        let base = canonical_root(&root);
+        assert_eq!(root.resolve("new.bin").unwrap(), base.join("new.bin"));
+        assert_eq!(
+            root.resolve("xilinx/new/shell.json").unwrap(),
+            base.join("xilinx/new/shell.json")
+        );
+    }
+
+    #[test]
+    fn rejects_empty_names() {
+        let (_dir, root) = setup();
+        for name in ["", ".", "./"] {
+            assert!(
+                matches!(root.resolve(name), Err(PathError::Empty)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_paths() {
+        let (_dir, root) = setup();
+        for name in ["/etc/shadow", "/lib/firmware/design.bit"] {
+            assert!(
+                matches!(root.resolve(name), Err(PathError::Absolute(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_parent_components() {
+        let (_dir, root) = setup();
+        for name in [
+            "../secret",
+            "../../etc/shadow",
+            "xilinx/../design.bit",
+            "xilinx/..",
+        ] {
+            assert!(
+                matches!(root.resolve(name), Err(PathError::ParentDir(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_symlink_escapes() {
+        let (dir, root) = setup();
+        symlink(dir.path().join("secret"), root.as_path().join("link.bin")).unwrap();
+        symlink(dir.path(), root.as_path().join("outside")).unwrap();
+        symlink(
+            dir.path().join("missing"),
+            root.as_path().join("dangling.bin"),
+        )
+        .unwrap();
+        for name in [
+            "link.bin",
+            "outside/secret",
+            "outside/new.bin",
+            "dangling.bin",
+        ] {
+            assert!(
+                matches!(root.resolve(name), Err(PathError::Escape(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn follows_symlinks_inside_the_root() {
+        let (_dir, root) = setup();
+        let base = canonical_root(&root);
+        symlink("xilinx/k26/k26.bin", root.as_path().join("current.bin")).unwrap();
+        symlink("xilinx/k26", root.as_path().join("k26")).unwrap();
+        assert_eq!(
+            root.resolve("current.bin").unwrap(),
+            base.join("xilinx/k26/k26.bin")
+        );
+        assert_eq!(
+            root.resolve("k26/new.dtbo").unwrap(),
+            base.join("xilinx/k26/new.dtbo")
+        );
+    }
+
+    #[test]
+    fn accepts_a_symlinked_root() {
+        let (dir, root) = setup();
+        let link = dir.path().join("firmware-link");
+        symlink(root.as_path(), &link).unwrap();
+        let linked = FirmwareRoot::new(&link);
+        assert_eq!(
+            linked.resolve("design.bit").unwrap(),
+            canonical_root(&root).join("design.bit")
+        );
+    }
+
+    #[test]
+    fn entries_keep_the_last_symlink() {
+        let (dir, root) = setup();
+        let base = canonical_root(&root);
+        symlink(dir.path().join("secret"), root.as_path().join("link.bin")).unwrap();
+        symlink(dir.path(), root.as_path().join("outside")).unwrap();
+        assert_eq!(
+            root.resolve_entry("link.bin").unwrap(),
+            base.join("link.bin")
+        );
+        assert_eq!(
+            root.resolve_entry("xilinx/k26/k26.bin").unwrap(),
+            base.join("xilinx/k26/k26.bin")
+        );
+        assert!(matches!(
+            root.resolve_entry("outside/secret"),
+            Err(PathError::Escape(_))
+        ));
+        assert!(matches!(
+            root.resolve_entry("../secret"),
+            Err(PathError::ParentDir(_))
+        ));
+    }
+
+    #[test]
+    fn components_must_be_single_names() {
+        assert!(check_component("k26").is_ok());
+        assert!(matches!(
+            check_component("xilinx/k26"),
+            Err(PathError::NotComponent(_))
+        ));
+        assert!(matches!(
+            check_component(".."),
+            Err(PathError::ParentDir(_))
+        ));
+        assert!(matches!(
+            check_component("/k26"),
+            Err(PathError::Absolute(_))
+        ));
+        assert!(matches!(check_component(""), Err(PathError::Empty)));
+    }
+}
@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use tracing::{debug, info};
+
+use super::path::FirmwareRoot;
+use super::PartialUpload;
+use crate::config::{FirmwareConfig, FirmwareStaging};
+use crate::logging;
+use crate::platform::{FIRMWARE_CLASS_PATH, KERNEL_FIRMWARE_DIR, PlatformBackend};
+
+/// Turns names in the firmware directory into names the kernel can load. The
+/// FPGA manager, configfs overlays and remoteproc look them up in the kernel
+/// firmware directory.
+#[derive(Debug, Clone, Default)]
+pub enum Stager {
+    /// the kernel searches the firmware directory itself
+    #[default]
+    Direct,
+    /// files are copied to `dir` below the kernel firmware directory and
+    /// loaded as `<prefix>/<name>`
+    Copy { dir: PathBuf, prefix: String },
+}
+
+fn same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+impl Stager {
+    pub fn new(
+        root: &FirmwareRoot,
+        config: &FirmwareConfig,
+        platform: &dyn PlatformBackend,
+    ) -> Result<Self, Box<dyn Error>> {
+        let kernel_dir = platform.system_path(KERNEL_FIRMWARE_DIR);
+        if same_dir(root.as_path(), &kernel_dir) {
+            return Ok(Stager::Direct);
+        }
+        match config.staging {
+            // the kernel tries this directory before /lib/firmware
+            FirmwareStaging::ClassPath => {
+                let dir = root.canonical()?;
+                let param = platform.system_path(FIRMWARE_CLASS_PATH);
+                platform
+                    .write_file(&param.to_string_lossy(), dir.to_string_lossy().as_bytes())
+                    .map_err(|e| format!("failed to set {}: {}", FIRMWARE_CLASS_PATH, e))?;
+                info!(target: logging::FIRMWARE, dir = %dir.display(), "firmware_class.path set");
+                Ok(Stager::Direct)
+            }
+            FirmwareStaging::Copy => {
+                let prefix = config.staging_dir.trim_matches('/').to_string();
+                Ok(Stager::Copy {
+                    dir: kernel_dir.join(&prefix),
+                    prefix,
+                })
+            }
+        }
+    }
+
+    /// Name to hand to the platform backend for `name`, copying the file first if needed.
+    pub fn stage(
+        &self,
+        root: &FirmwareRoot,
+        platform: &dyn PlatformBackend,
+        name: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let Stager::Copy { dir, prefix } = self else {
+            return Ok(name.to_string());
+        };
+        let source = root.resolve(name)?;
+        let relative = source.strip_prefix(root.canonical()?)?;
+        let dest = dir.join(relative);
+        if let Some(parent) = dest.parent() {
+            platform.create_dir(parent)?;
+        }
+        // a load running at the same time must not see a half written copy
+        let upload = PartialUpload::new(&dest.to_string_lossy());
+        if let Err(e) = platform.copy_file(&source, Path::new(upload.temp_path())) {
+            upload.discard(platform);
+            return Err(e);
+        }
+        upload.commit(platform)?;
+        debug!(target: logging::FIRMWARE, name, staged = %dest.display(), "firmware staged");
+        Ok(format!("{}/{}", prefix, relative.to_string_lossy()))
+    }
+
+    /// Removes the staged copy of a file removed from the firmware directory.
+    pub fn unstage(
+        &self,
+        root: &FirmwareRoot,
+        platform: &dyn PlatformBackend,
+        path: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        let Stager::Copy { dir, .. } = self else {
+            return Ok(());
+        };
+        let dest = dir.join(path.strip_prefix(root.canonical()?)?);
+        if dest.symlink_metadata().is_ok() {
+            platform.remove_file(&dest)?;
+            debug!(target: logging::FIRMWARE, staged = %dest.display(), "staged firmware removed");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use tokio::io::unix::AsyncFd;
+
+const UIO_CLASS: &str = "/sys/class/uio";
+
+pub fn find_uio_device(name: &str) -> io::Result<PathBuf> {
+    for entry in fs::read_dir(UIO_CLASS)? {
+        let entry = entry?;
+        let uio_name = fs::read_to_string(entry.path().join("name"))?;
+        if uio_name.trim() == name {
+            return Ok(PathBuf::from("/dev").join(entry.file_name()));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("uio device not found: {}", name),
+    ))
+}
+
+// Interrupt handling through the /dev/uioN character device of uio_pdrv_genirq.
+// Writing 1/0 enables/disables the IRQ, reading blocks until the next interrupt
+// and returns the total interrupt count.
+#[derive(Debug)]
+pub struct UioIrq {
+    fd: AsyncFd<File>,
+}
+
+impl UioIrq {
+    pub fn open(name: &str) -> io::Result<Self> {
+        let path = find_uio_device(name)?;
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)?;
+        Ok(UioIrq {
+            fd: AsyncFd::new(file)?,
+        })
+    }
+
+    pub fn set_enable(&self, enable: bool) -> io::Result<()> {
+        let value: u32 = if enable { 1 } else { 0 };
+        let mut file = self.fd.get_ref();
+        file.write_all(&value.to_ne_bytes())
+    }
+
+    pub async fn wait(&self) -> io::Result<u32> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let result = guard.try_io(|fd| {
+                let mut buf = [0u8; 4];
+                let mut file = fd.get_ref();
+                match file.read(&mut buf)? {
+                    4 => Ok(u32::from_ne_bytes(buf)),
+                    n => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("short read from uio device: {} bytes", n),
+                    )),
+                }
+            });
+            if let Ok(result) = result {
+                return result;
+            }
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use std::error::Error;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tonic::Code;
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tower::{Layer, Service};
+use tracing::field::Empty;
+use tracing::{Instrument, Span, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::config::{LogConfig, LogFormat};
+use crate::metrics;
+
+// targets usable as per-subsystem filters, e.g. `firmware = "debug"`
+pub const RPC: &str = "rpc";
+pub const SERVER: &str = "server";
+pub const SESSION: &str = "session";
+pub const ACCESSOR: &str = "accessor";
+pub const FIRMWARE: &str = "firmware";
+pub const FPGA: &str = "fpga";
+pub const REMOTEPROC: &str = "remoteproc";
+
+// without an explicit level the old verbose levels keep their meaning
+fn default_level(verbose: i32) -> &'static str {
+    match verbose {
+        i32::MIN..=0 => "warn",
+        1 => "info",
+        _ => "debug",
+    }
+}
+
+// RUST_LOG, when set, replaces the configured filters entirely
+fn filter(config: &LogConfig, verbose: i32) -> Result<EnvFilter, Box<dyn Error>> {
+    if std::env::var_os(EnvFilter::DEFAULT_ENV).is_some() {
+        return Ok(EnvFilter::try_from_default_env()?);
+    }
+    let level = config.level.as_deref().unwrap_or(default_level(verbose));
+    let mut filter = EnvFilter::try_new(level)?;
+    for (target, level) in &config.filters {
+        filter = filter.add_directive(format!("{}={}", target, level).parse()?);
+    }
+    Ok(filter)
+}
+
+pub fn init(config: &LogConfig, verbose: i32) -> Result<(), Box<dyn Error>> {
+    let registry = tracing_subscriber::registry().with(filter(config, verbose)?);
+    match config.format {
+        LogFormat::Text => registry.with(tracing_subscriber::fmt::layer()).try_init()?,
+        LogFormat::Json => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_current_span(true),
+            )
+            .try_init()?,
+        LogFormat::Journald => registry.with(tracing_journald::layer()?).try_init()?,
+    }
+    Ok(())
+}
+
+fn peer_addr<B>(request: &http::Request<B>) -> Option<SocketAddr> {
+    let extensions = request.extensions();
+    extensions
+        .get::<TcpConnectInfo>()
+        .and_then(|info| info.remote_addr())
+        .or_else(|| {
+            extensions
+                .get::<TlsConnectInfo<TcpConnectInfo>>()
+                .and_then(|info| info.get_ref().remote_addr())
+        })
+}
+
+fn rpc_name<B>(request: &http::Request<B>) -> String {
+    let path = request.uri().path();
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+fn request_span<B>(request: &http::Request<B>, rpc: &str) -> Span {
+    let peer = peer_addr(request).map(|addr| addr.to_string());
+    info_span!(
+        target: RPC,
+        "rpc",
+        rpc,
+        peer = peer.as_deref().unwrap_or("-"),
+        id = Empty,
+    )
+}
+
+// handlers call this so the request span carries the accessor id
+pub fn record_id(id: u32) {
+    Span::current().record("id", id);
+}
+
+// Statuses returned by a handler travel in the response headers; errors on a
+// stream after it started are only visible to the client.
+fn response_code<B>(response: &http::Response<B>) -> Code {
+    response
+        .headers()
+        .get("grpc-status")
+        .map(|value| Code::from_bytes(value.as_bytes()))
+        .unwrap_or(Code::Ok)
+}
+
+/// Wraps every request in a span (RPC name, peer, accessor id) and logs its
+/// latency and outcome when the response is ready. The same observation feeds
+/// the per-method RPC metrics.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLogLayer;
+
+impl<S> Layer<S> for RequestLogLayer {
+    type Service = RequestLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestLog { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestLog<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RequestLog<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let rpc = rpc_name(&request);
+        let span = request_span(&request, &rpc);
+        let start = Instant::now();
+        let future = {
+            let _enter = span.enter();
+            self.inner.call(request)
+        };
+        Box::pin(
+            async move {
+                let result = future.await;
+                let elapsed = start.elapsed();
+                let latency_us = elapsed.as_micros() as u64;
+                // transport errors never reached a handler, report them as unavailable
+                let code = match &result {
+                    Ok(response) => response_code(response),
+                    Err(_) => Code::Unavailable,
+                };
+                match (&result, code) {
+                    (Ok(_), Code::Ok) => {
+                        info!(target: RPC, latency_us, outcome = "ok", "request completed")
+                    }
+                    (Ok(_), code) => warn!(
+                        target: RPC,
+                        latency_us,
+                        outcome = ?code,
+                        "request failed"
+                    ),
+                    (Err(_), _) => warn!(
+                        target: RPC,
+                        latency_us,
+                        outcome = "transport error",
+                        "request failed"
+                    ),
+                }
+                metrics::observe_rpc(&rpc, code, elapsed);
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
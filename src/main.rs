@@ -1,12 +1,24 @@
-use jelly_fpgautil as fpgautil;
-use jelly_uidmng as uidmng;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
-use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic::server::NamedService;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tonic::{Request, Response, Status, Streaming};
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::{debug, info, trace, warn};
 
 pub mod jelly_fpga_control {
     tonic::include_proto!("jelly_fpga_control");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("jelly_fpga_control_descriptor");
 }
 
 use jelly_fpga_control::jelly_fpga_control_server::*;
@@ -15,17 +27,348 @@ use jelly_fpga_control::*;
 mod accessor;
 use accessor::Accessor;
 
+mod audit;
+use audit::AuditLog;
+
+mod auth;
+use auth::{AuthInterceptor, Role};
+
+mod config;
+use config::{
+    AccessConfig, Backend, Config, FeatureConfig, FirmwareStaging, LogFormat, SecurityConfig,
+};
+
+mod error;
+
+mod firmware;
+use firmware::path::FirmwareRoot;
+use firmware::staging::Stager;
+
+mod irq;
+
+mod logging;
+use logging::RequestLogLayer;
+
+mod metrics;
+
+mod platform;
+use platform::PlatformBackend;
+
+mod session;
+use session::{SessionId, SessionManager};
+
+#[cfg(test)]
+mod tests;
+
+const REGISTER_SESSION_QUEUE: usize = 256;
+const MEM_STREAM_QUEUE: usize = 4;
+const MEM_STREAM_DEFAULT_CHUNK: usize = 64 * 1024;
+const MEM_STREAM_MAX_CHUNK: usize = 1024 * 1024;
+const WAIT_DEFAULT_INTERVAL: Duration = Duration::from_millis(1);
+const IRQ_EVENT_QUEUE: usize = 64;
+const SESSION_DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const JELLY_FPGA_CONTROL_SERVICE: &str =
+    <JellyFpgaControlServer<JellyFpgaControlService> as NamedService>::NAME;
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const DTC_COMMAND: &str = "dtc";
+
+fn require_feature(enabled: bool, feature: &str) -> Result<(), Status> {
+    if enabled {
+        Ok(())
+    } else {
+        Err(error::feature_status(feature))
+    }
+}
+
+// accelerator names become a directory of their own, not a path
+fn check_accel_name(field: &str, name: &str) -> Result<(), Status> {
+    firmware::path::check_component(name).map_err(|e| error::firmware_name_status(field, e))
+}
+
+fn has_command(name: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|dir| dir.join(name).is_file())
+    })
+}
+
+// the service can only do its job when the FPGA manager and dtc are available
+fn serving_status(platform: &dyn PlatformBackend) -> ServingStatus {
+    if platform.has_fpga_manager() && has_command(DTC_COMMAND) {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    }
+}
+
+// tools may be installed or the FPGA manager probed after start, so keep re-checking
+fn spawn_health_check(reporter: HealthReporter, platform: Arc<dyn PlatformBackend>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        let mut last = None;
+        loop {
+            interval.tick().await;
+            let status = serving_status(platform.as_ref());
+            if last != Some(status) {
+                info!(target: logging::SERVER, status = ?status, "health");
+                reporter.set_service_status("", status).await;
+                reporter
+                    .set_service_status(JELLY_FPGA_CONTROL_SERVICE, status)
+                    .await;
+                last = Some(status);
+            }
+        }
+    });
+}
+
+// u64 fields of open requests, on 32-bit targets they must not wrap
+fn wire_usize(field: &str, value: u64) -> Result<usize, Status> {
+    usize::try_from(value)
+        .map_err(|_| error::bad_request_status(field, "exceeds the server's address space"))
+}
+
+fn batch_op(op: &BatchOp) -> accessor::BatchOp {
+    let kind = match op.r#type() {
+        BatchOpType::Read => accessor::BatchKind::Read,
+        BatchOpType::Write => accessor::BatchKind::Write(op.data),
+        BatchOpType::Modify => accessor::BatchKind::Modify {
+            data: op.data,
+            mask: op.mask,
+        },
+    };
+    accessor::BatchOp {
+        id: op.id as accessor::Id,
+        reg: op.reg,
+        address: op.address,
+        size: op.size,
+        kind,
+    }
+}
+
+// failed batch operations as a status, for the audit log
+fn batch_status(result: &BatchOpResult) -> Result<(), Status> {
+    if result.result {
+        Ok(())
+    } else {
+        Err(Status::new(tonic::Code::from(result.code), result.message.clone()))
+    }
+}
+
+fn batch_args(op: &accessor::BatchOp) -> serde_json::Value {
+    let position = if op.reg { "reg" } else { "offset" };
+    let mut args = json!({ "id": op.id, position: op.address, "size": op.size });
+    match op.kind {
+        accessor::BatchKind::Read => {}
+        accessor::BatchKind::Write(data) => args["data"] = json!(data),
+        accessor::BatchKind::Modify { data, mask } => {
+            args["data"] = json!(data);
+            args["mask"] = json!(mask);
+        }
+    }
+    args
+}
+
+struct WaitSpec {
+    condition: WaitCondition,
+    mask: u64,
+    expected: u64,
+    interval: Duration,
+    timeout: Duration,
+}
+
+impl WaitSpec {
+    fn new(
+        condition: WaitCondition,
+        mask: u64,
+        expected: u64,
+        interval_us: u64,
+        timeout_ms: u64,
+    ) -> Self {
+        WaitSpec {
+            condition,
+            mask,
+            expected,
+            interval: match interval_us {
+                0 => WAIT_DEFAULT_INTERVAL,
+                us => Duration::from_micros(us),
+            },
+            timeout: Duration::from_millis(timeout_ms),
+        }
+    }
+
+    fn is_met(&self, data: u64) -> bool {
+        let data = data & self.mask;
+        match self.condition {
+            WaitCondition::Eq => data == self.expected,
+            WaitCondition::Ne => data != self.expected,
+            WaitCondition::Ge => data >= self.expected,
+            WaitCondition::Lt => data < self.expected,
+        }
+    }
+}
+
+fn status_result(status: &Status) -> BatchOpResult {
+    BatchOpResult {
+        result: false,
+        data: 0,
+        code: status.code() as i32,
+        message: status.message().to_string(),
+    }
+}
+
+fn batch_result(result: Result<u64, accessor::AccessorError>) -> BatchOpResult {
+    match result {
+        Ok(data) => BatchOpResult {
+            result: true,
+            data,
+            ..Default::default()
+        },
+        Err(e) => status_result(&Status::from(e)),
+    }
+}
+
+// what the server last programmed into the default FPGA manager
 #[derive(Debug, Default)]
+struct LastLoad {
+    firmware: String,
+    accel: String,
+    time: Option<SystemTime>,
+}
+
+impl LastLoad {
+    fn set(&mut self, firmware: &str, accel: &str) {
+        self.firmware = firmware.to_string();
+        self.accel = accel.to_string();
+        self.time = Some(SystemTime::now());
+    }
+}
+
+#[derive(Debug)]
 struct JellyFpgaControlService {
-    verbose: i32,
+    platform: Arc<dyn PlatformBackend>,
+    last_load: std::sync::Mutex<LastLoad>,
+    firmware: FirmwareRoot,
+    stager: Stager,
+    access: AccessConfig,
+    features: FeatureConfig,
     accessor: Arc<RwLock<Accessor>>,
+    sessions: Arc<Mutex<SessionManager>>,
+    audit: Arc<AuditLog>,
 }
 
 impl JellyFpgaControlService {
-    pub fn new(verbose: i32) -> Self {
+    pub fn new(
+        config: &Config,
+        platform: Arc<dyn PlatformBackend>,
+        stager: Stager,
+        audit: AuditLog,
+    ) -> Self {
         JellyFpgaControlService {
-            verbose,
+            platform,
+            last_load: std::sync::Mutex::new(LastLoad::default()),
+            firmware: FirmwareRoot::new(&config.firmware.dir),
+            stager,
+            access: config.access.clone(),
+            features: config.features.clone(),
             accessor: Arc::new(RwLock::new(Accessor::new())),
+            sessions: Arc::new(Mutex::new(SessionManager::new())),
+            audit: Arc::new(audit),
+        }
+    }
+
+    // closes sessions whose keepalive expired together with their handles
+    pub fn spawn_session_expiry(&self) {
+        let sessions = self.sessions.clone();
+        let accessor = self.accessor.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let expired = sessions.lock().await.expire(Instant::now());
+                if expired.is_empty() {
+                    continue;
+                }
+                let mut accessor = accessor.write().await;
+                for session in expired {
+                    let count = accessor.close_owned(session);
+                    debug!(target: logging::SESSION, session, closed = count, "session expired");
+                }
+            }
+        });
+    }
+
+    fn record_load(&self, firmware: &str, accel: &str) {
+        let mut last_load = self.last_load.lock().unwrap_or_else(|e| e.into_inner());
+        last_load.set(firmware, accel);
+    }
+
+    // every firmware name a client sends is resolved here, it must stay inside the directory
+    fn firmware_path(&self, field: &str, name: &str) -> Result<String, Status> {
+        let path = self
+            .firmware
+            .resolve(name)
+            .map_err(|e| error::firmware_name_status(field, e))?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    // the platform loads by name from the kernel firmware path, which may be elsewhere
+    fn stage_firmware(&self, field: &str, name: &str) -> Result<String, Status> {
+        self.firmware_path(field, name)?;
+        self.stager
+            .stage(&self.firmware, self.platform.as_ref(), name)
+            .map_err(|e| error::platform_status("stage_firmware", e))
+    }
+
+    async fn session(&self, metadata: &MetadataMap) -> Result<SessionId, Status> {
+        let session = session::session_id(metadata)?;
+        if session != session::ANONYMOUS_SESSION && !self.sessions.lock().await.touch(session) {
+            return Err(error::session_status(session));
+        }
+        Ok(session)
+    }
+
+    async fn close_session(&self, session: SessionId) -> bool {
+        if !self.sessions.lock().await.close(session) {
+            return false;
+        }
+        self.accessor.write().await.close_owned(session);
+        true
+    }
+
+    async fn uio_irq(
+        &self,
+        session: SessionId,
+        id: accessor::Id,
+    ) -> Result<Arc<irq::UioIrq>, Status> {
+        let accessor = self.accessor.read().await;
+        accessor.check_owner(id, session)?;
+        Ok(accessor.uio_irq(id)?)
+    }
+
+    // the lock is only held while reading so other clients can keep working during the wait
+    async fn wait_value(
+        &self,
+        session: SessionId,
+        op: accessor::BatchOp,
+        spec: WaitSpec,
+    ) -> Result<WaitResponse, Status> {
+        let start = Instant::now();
+        loop {
+            let data = {
+                let mut accessor = self.accessor.write().await;
+                unsafe { accessor.execute(session, &op) }?
+            };
+            let elapsed = start.elapsed();
+            let result = spec.is_met(data);
+            if result || elapsed >= spec.timeout {
+                return Ok(WaitResponse {
+                    result,
+                    data,
+                    timed_out: !result,
+                    elapsed_us: elapsed.as_micros() as u64,
+                });
+            }
+            tokio::time::sleep(spec.interval.min(spec.timeout - elapsed)).await;
         }
     }
 }
@@ -34,11 +377,10 @@ impl JellyFpgaControlService {
 impl JellyFpgaControl for JellyFpgaControlService {
     async fn get_version(
         &self,
-        _request: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<VersionResponse>, Status> {
-        if self.verbose >= 1 {
-            println!("get_version");
-        }
+        auth::require(&request, Role::ReadOnly)?;
+        debug!(target: logging::SERVER, "get_version");
         Ok(Response::new(VersionResponse {
             version: env!("CARGO_PKG_VERSION").to_string(),
         }))
@@ -46,121 +388,334 @@ impl JellyFpgaControl for JellyFpgaControlService {
 
     async fn reset(
         &self,
-        _request: Request<ResetRequest>,
+        request: Request<ResetRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
-        if self.verbose >= 1 {
-            println!("reset");
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin);
+        let mut args = json!({});
+        let result = async {
+            allowed?;
+            let session = self.session(request.metadata()).await?;
+            debug!(target: logging::ACCESSOR, session, "reset");
+            let mut accessor = self.accessor.write().await;
+            let closed = accessor.close_owned(session);
+            args = json!({ "session": session, "closed": closed });
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        accessor.close_all();
+        .await;
+        self.audit.record(&caller, "Reset", args, &result);
+        result?;
         Ok(Response::new(BoolResponse { result: true }))
     }
 
-    async fn load(&self, request: Request<LoadRequest>) -> Result<Response<LoadResponse>, Status> {
+    async fn open_session(
+        &self,
+        request: Request<OpenSessionRequest>,
+    ) -> Result<Response<OpenSessionResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let req = request.into_inner();
+        let timeout = match req.timeout_ms {
+            0 => SESSION_DEFAULT_TIMEOUT,
+            ms => Duration::from_millis(ms),
+        };
+        let session_id = self.sessions.lock().await.open(timeout);
+        debug!(
+            target: logging::SESSION,
+            session = session_id,
+            timeout_ms = timeout.as_millis(),
+            "open_session"
+        );
+        Ok(Response::new(OpenSessionResponse {
+            result: true,
+            session_id,
+            timeout_ms: timeout.as_millis() as u64,
+        }))
+    }
+
+    async fn keep_alive(
+        &self,
+        request: Request<SessionRequest>,
+    ) -> Result<Response<BoolResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("load: name={}", req.name);
+        trace!(target: logging::SESSION, session = req.session_id, "keep_alive");
+        if !self.sessions.lock().await.touch(req.session_id) {
+            return Err(error::session_status(req.session_id));
         }
-        let result = fpgautil::load(&req.name);
-        if let Ok(slot) = result {
-            Ok(Response::new(LoadResponse {
-                result: true,
-                slot: slot,
-            }))
-        } else {
-            Ok(Response::new(LoadResponse {
-                result: false,
-                slot: -1,
-            }))
+        Ok(Response::new(BoolResponse { result: true }))
+    }
+
+    async fn close_session(
+        &self,
+        request: Request<SessionRequest>,
+    ) -> Result<Response<BoolResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let req = request.into_inner();
+        debug!(target: logging::SESSION, session = req.session_id, "close_session");
+        if !JellyFpgaControlService::close_session(self, req.session_id).await {
+            return Err(error::session_status(req.session_id));
         }
+        Ok(Response::new(BoolResponse { result: true }))
+    }
+
+    type WatchSessionStream = ReceiverStream<Result<SessionEvent, Status>>;
+
+    // the session lives as long as this stream, so a dropped connection closes it
+    async fn watch_session(
+        &self,
+        request: Request<SessionRequest>,
+    ) -> Result<Response<Self::WatchSessionStream>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let req = request.into_inner();
+        debug!(target: logging::SESSION, session = req.session_id, "watch_session");
+        let session_id = req.session_id;
+        if !self.sessions.lock().await.set_watched(session_id, true) {
+            return Err(error::session_status(session_id));
+        }
+        let sessions = self.sessions.clone();
+        let accessor = self.accessor.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SESSION_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = tx.closed() => {
+                        if sessions.lock().await.close(session_id) {
+                            accessor.write().await.close_owned(session_id);
+                        }
+                        debug!(
+                            target: logging::SESSION,
+                            session = session_id,
+                            "watch_session disconnected"
+                        );
+                        break;
+                    }
+                    _ = interval.tick() => {
+                        if !sessions.lock().await.contains(session_id) {
+                            let event = SessionEvent {
+                                session_id,
+                                closed: true,
+                            };
+                            let _ = tx.send(Ok(event)).await;
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn share_accessor(
+        &self,
+        request: Request<ShareAccessorRequest>,
+    ) -> Result<Response<BoolResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(target: logging::ACCESSOR, id = req.id, shared = req.shared, "share_accessor");
+        logging::record_id(req.id);
+        let mut accessor = self.accessor.write().await;
+        accessor.set_shared(req.id as accessor::Id, session, req.shared)?;
+        Ok(Response::new(BoolResponse { result: true }))
+    }
+
+    async fn load(&self, request: Request<LoadRequest>) -> Result<Response<LoadResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.fpga, "fpga"));
+        let req = request.into_inner();
+        debug!(target: logging::FPGA, name = req.name, "load");
+        let slot = (|| {
+            allowed?;
+            check_accel_name("name", &req.name)?;
+            let start = Instant::now();
+            let slot = self.platform.load(&req.name);
+            metrics::observe_bitstream_load("Load", slot.is_ok(), start.elapsed());
+            slot.map_err(|e| error::platform_status("load", e))
+        })();
+        self.audit.record(&caller, "Load", json!({ "name": req.name }), &slot);
+        let slot = slot?;
+        self.record_load("", &req.name);
+        Ok(Response::new(LoadResponse { result: true, slot }))
     }
 
     async fn unload(
         &self,
         request: Request<UnloadRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.fpga, "fpga"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("unload: slot={}", req.slot);
-        }
-        let result = fpgautil::unload(req.slot);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(target: logging::FPGA, slot = req.slot, "unload");
+        let result = allowed.and_then(|()| {
+            self.platform
+                .unload(req.slot)
+                .map_err(|e| error::platform_status("unload", e))
+        });
+        let args = json!({ "slot": req.slot });
+        self.audit.record(&caller, "Unload", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn register_accel(
         &self,
         request: Request<RegisterAccelRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.fpga, "fpga"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("register_accel: accel_name={}", req.accel_name);
-        }
-        let json_file = if req.json_file.is_empty() {
-            None
-        } else {
-            Some(format!("/lib/firmware/{}", req.json_file))
-        };
-        let bin_file = format!("/lib/firmware/{}", req.bin_file);
-        let dtbo_file = format!("/lib/firmware/{}", req.dtbo_file);
-        let result = fpgautil::register_accel(
-            &req.accel_name,
-            &bin_file,
-            &dtbo_file,
-            json_file.as_deref(),
-            req.overwrite,
-        );
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(target: logging::FPGA, accel_name = req.accel_name, "register_accel");
+        let result = (|| {
+            allowed?;
+            check_accel_name("accel_name", &req.accel_name)?;
+            let json_file = if req.json_file.is_empty() {
+                None
+            } else {
+                Some(self.firmware_path("json_file", &req.json_file)?)
+            };
+            let bin_file = self.firmware_path("bin_file", &req.bin_file)?;
+            let dtbo_file = self.firmware_path("dtbo_file", &req.dtbo_file)?;
+            self.platform
+                .register_accel(
+                    &req.accel_name,
+                    &bin_file,
+                    &dtbo_file,
+                    json_file.as_deref(),
+                    req.overwrite,
+                )
+                .map_err(|e| error::platform_status("register_accel", e))
+        })();
+        let args = json!({
+            "accel_name": req.accel_name,
+            "bin_file": req.bin_file,
+            "dtbo_file": req.dtbo_file,
+            "json_file": req.json_file,
+            "overwrite": req.overwrite,
+        });
+        self.audit.record(&caller, "RegisterAccel", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn unregister_accel(
         &self,
         request: Request<UnregisterAccelRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.fpga, "fpga"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("unregister_accel: accel_name={}", req.accel_name);
-        }
-        let result = fpgautil::unregister_accel(&req.accel_name);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(target: logging::FPGA, accel_name = req.accel_name, "unregister_accel");
+        let result = (|| {
+            allowed?;
+            check_accel_name("accel_name", &req.accel_name)?;
+            self.platform
+                .unregister_accel(&req.accel_name)
+                .map_err(|e| error::platform_status("unregister_accel", e))
+        })();
+        let args = json!({ "accel_name": req.accel_name });
+        self.audit.record(&caller, "UnregisterAccel", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn upload_firmware(
         &self,
         request: Request<Streaming<UploadFirmwareRequest>>,
     ) -> Result<Response<BoolResponse>, Status> {
-        if self.verbose >= 1 {
-            println!("upload_firmware");
-        }
-
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.firmware, "firmware"));
+        debug!(target: logging::FIRMWARE, "upload_firmware");
         let mut stream = request.into_inner();
 
-        let mut first = true;
-        while let Some(msg) = stream.next().await {
-            if self.verbose >= 2 {
-                print!(".");
+        let mut name = String::new();
+        let mut size = 0;
+        let mut partial: Option<firmware::PartialUpload> = None;
+        let result = async {
+            allowed?;
+            let mut expected_sha256 = String::new();
+            let mut expected_size = None;
+            let mut hasher = Sha256::new();
+            while let Some(msg) = stream.next().await {
+                let msg = msg?;
+                trace!(
+                    target: logging::FIRMWARE,
+                    name = msg.name,
+                    len = msg.data.len(),
+                    "upload_firmware chunk"
+                );
+                match &partial {
+                    Some(upload) => {
+                        if !msg.name.is_empty() && msg.name != name {
+                            return Err(error::bad_request_status(
+                                "name",
+                                "must not change within an upload",
+                            ));
+                        }
+                        self.platform.append_file(upload.temp_path(), &msg.data)
+                    }
+                    None => {
+                        name = msg.name.clone();
+                        expected_sha256 = msg.sha256.to_ascii_lowercase();
+                        expected_size = msg.has_size.then_some(msg.size);
+                        let dest = self.firmware_path("name", &name)?;
+                        let upload = firmware::PartialUpload::new(&dest);
+                        self.platform
+                            .write_file(partial.insert(upload).temp_path(), &msg.data)
+                    }
+                }
+                .map_err(|e| error::platform_status("upload_firmware", e))?;
+                hasher.update(&msg.data);
+                size += msg.data.len() as u64;
+                if let Some(expected) = expected_size
+                    && size > expected
+                {
+                    return Err(error::checksum_status(
+                        "size",
+                        &expected.to_string(),
+                        &format!(">={}", size),
+                    ));
+                }
             }
-            let msg = msg?;
-            let name = format!("/lib/firmware/{}", msg.name);
-            if let Err(e) = if first {
-                uidmng::write_sudo(&name, &msg.data)
-            } else {
-                uidmng::append_sudo(&name, &msg.data)
-            } {
-                println!("Error:{}", e);
-                return Ok(Response::new(BoolResponse { result: false }));
+
+            let Some(upload) = partial.take() else {
+                return Err(error::bad_request_status("name", "no firmware was sent"));
+            };
+            if let Some(expected) = expected_size
+                && expected != size
+            {
+                return Err(error::checksum_status(
+                    "size",
+                    &expected.to_string(),
+                    &size.to_string(),
+                ));
+            }
+            let sha256 = firmware::sha256_hex(hasher);
+            if !expected_sha256.is_empty() && expected_sha256 != sha256 {
+                return Err(error::checksum_status("sha256", &expected_sha256, &sha256));
             }
-            first = false;
+            upload
+                .commit(self.platform.as_ref())
+                .map_err(|e| error::platform_status("upload_firmware", e))?;
+            Ok(sha256)
         }
-
-        if self.verbose >= 2 {
-            println!("done");
+        .await;
+        if let Some(upload) = partial {
+            upload.discard(self.platform.as_ref());
         }
+        let sha256 = result.as_deref().unwrap_or_default();
+        let args = json!({ "name": name, "size": size, "sha256": sha256 });
+        self.audit.record(&caller, "UploadFirmware", args, &result);
+        result?;
+        // only uploads that were committed are counted
+        metrics::add_firmware_upload(size);
+
+        debug!(target: logging::FIRMWARE, name, size, "upload_firmware done");
 
         Ok(Response::new(BoolResponse { result: true }))
     }
@@ -169,41 +724,253 @@ impl JellyFpgaControl for JellyFpgaControlService {
         &self,
         request: Request<RemoveFirmwareRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.firmware, "firmware"));
+        let req = request.into_inner();
+        debug!(target: logging::FIRMWARE, name = req.name, "remove_firmware");
+        let result = (|| {
+            allowed?;
+            let path = self
+                .firmware
+                .resolve_entry(&req.name)
+                .map_err(|e| error::firmware_name_status("name", e))?;
+            self.platform
+                .remove_file(&path)
+                .and_then(|()| self.stager.unstage(&self.firmware, self.platform.as_ref(), &path))
+                .map_err(|e| error::platform_status("remove_firmware", e))
+        })();
+        let args = json!({ "name": req.name });
+        self.audit.record(&caller, "RemoveFirmware", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
+    }
+
+    async fn list_firmware(
+        &self,
+        request: Request<ListFirmwareRequest>,
+    ) -> Result<Response<ListFirmwareResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("remove_firmware: name={}", req.name);
+        debug!(
+            target: logging::FIRMWARE,
+            pattern = req.pattern,
+            recursive = req.recursive,
+            "list_firmware"
+        );
+        if req.pattern.len() > firmware::MAX_PATTERN_LEN {
+            return Err(error::bad_request_status(
+                "pattern",
+                &format!("longer than {} bytes", firmware::MAX_PATTERN_LEN),
+            ));
         }
-        let result = fpgautil::remove_firmware(&req.name);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
+        // hashing can take a while on large directories
+        let root = self.firmware.clone();
+        let files = tokio::task::spawn_blocking(move || {
+            firmware::list(&root, &req.pattern, req.recursive)
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| error::platform_status("list_firmware", e.into()))?;
+        Ok(Response::new(ListFirmwareResponse {
+            result: true,
+            files,
         }))
     }
 
+    type DownloadFirmwareStream = ReceiverStream<Result<DownloadFirmwareResponse, Status>>;
+
+    async fn download_firmware(
+        &self,
+        request: Request<DownloadFirmwareRequest>,
+    ) -> Result<Response<Self::DownloadFirmwareStream>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let req = request.into_inner();
+        debug!(
+            target: logging::FIRMWARE,
+            name = req.name,
+            offset = req.offset,
+            length = req.length,
+            "download_firmware"
+        );
+        let path = self.firmware_path("name", &req.name)?;
+        let mut file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|e| error::platform_status("download_firmware", e.into()))?;
+        let file_size = file
+            .metadata()
+            .await
+            .map_err(|e| error::platform_status("download_firmware", e.into()))?
+            .len();
+        let length = match req.length {
+            0 => file_size.saturating_sub(req.offset),
+            n => n,
+        };
+        if req.offset.checked_add(length).is_none_or(|end| end > file_size) {
+            return Err(error::file_range_status(&req.name, req.offset, length, file_size));
+        }
+        file.seek(std::io::SeekFrom::Start(req.offset))
+            .await
+            .map_err(|e| error::platform_status("download_firmware", e.into()))?;
+
+        // same chunking as the memory streams
+        let chunk_size = match req.chunk_size as usize {
+            0 => MEM_STREAM_DEFAULT_CHUNK,
+            n => n.min(MEM_STREAM_MAX_CHUNK),
+        } as u64;
+        let (tx, rx) = mpsc::channel(MEM_STREAM_QUEUE);
+        tokio::spawn(async move {
+            let mut hasher = Sha256::new();
+            let mut pos = 0;
+            // an empty range still ends with one message carrying the checksum
+            loop {
+                let len = chunk_size.min(length - pos) as usize;
+                let mut data = vec![0u8; len];
+                if let Err(e) = file.read_exact(&mut data).await {
+                    let status = error::platform_status("download_firmware", e.into());
+                    let _ = tx.send(Err(status)).await;
+                    return;
+                }
+                hasher.update(&data);
+                let chunk_offset = req.offset + pos;
+                pos += len as u64;
+                let last = pos >= length;
+                let sha256 = if last {
+                    firmware::sha256_hex(hasher.clone())
+                } else {
+                    String::new()
+                };
+                let msg = DownloadFirmwareResponse {
+                    offset: chunk_offset,
+                    data,
+                    last,
+                    file_size,
+                    sha256,
+                };
+                if tx.send(Ok(msg)).await.is_err() || last {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
     async fn load_bitstream(
         &self,
         request: Request<LoadBitstreamRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.fpga, "fpga"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("load_bitstream: name={}", req.name);
-        }
-        let result = fpgautil::load_bitstream_from_firmware(&req.name);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(target: logging::FPGA, name = req.name, "load_bitstream");
+        let result = (|| {
+            allowed?;
+            if !self.platform.has_fpga_manager() {
+                return Err(error::precondition_status(
+                    "FPGA_MANAGER",
+                    platform::FPGA_MANAGER_CLASS,
+                    "no FPGA manager is available",
+                ));
+            }
+            let name = self.stage_firmware("name", &req.name)?;
+            let start = Instant::now();
+            let result = self.platform.load_bitstream(&name);
+            metrics::observe_bitstream_load("LoadBitstream", result.is_ok(), start.elapsed());
+            result.map_err(|e| error::platform_status("load_bitstream", e))
+        })();
+        self.audit.record(&caller, "LoadBitstream", json!({ "name": req.name }), &result);
+        result?;
+        self.record_load(&req.name, "");
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn load_dtbo(
         &self,
         request: Request<LoadDtboRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.fpga, "fpga"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("load_dtbo: name={}", req.name);
-        }
-        let result = fpgautil::load_dtbo_from_firmware(&req.name);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
+        debug!(target: logging::FPGA, name = req.name, "load_dtbo");
+        let result = (|| {
+            allowed?;
+            let name = self.stage_firmware("name", &req.name)?;
+            let start = Instant::now();
+            let result = self.platform.load_dtbo(&name);
+            metrics::observe_bitstream_load("LoadDtbo", result.is_ok(), start.elapsed());
+            result.map_err(|e| error::platform_status("load_dtbo", e))
+        })();
+        self.audit.record(&caller, "LoadDtbo", json!({ "name": req.name }), &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
+    }
+
+    async fn get_fpga_status(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<GetFpgaStatusResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        debug!(target: logging::FPGA, "get_fpga_status");
+        let last_load = self.last_load.lock().unwrap_or_else(|e| e.into_inner());
+        let managers = self
+            .platform
+            .fpga_managers()
+            .into_iter()
+            .map(|manager| {
+                let mut status = FpgaManagerStatus {
+                    name: manager.name,
+                    description: manager.description,
+                    state: manager.state,
+                    flags: manager.flags,
+                    status: manager.status,
+                    ..Default::default()
+                };
+                if status.name == platform::DEFAULT_FPGA_MANAGER
+                    && let Some(time) = last_load.time
+                {
+                    status.firmware = last_load.firmware.clone();
+                    status.accel = last_load.accel.clone();
+                    status.loaded_ms = time
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |d| d.as_millis() as u64);
+                }
+                status
+            })
+            .collect();
+        Ok(Response::new(GetFpgaStatusResponse {
+            result: true,
+            managers,
+        }))
+    }
+
+    async fn list_overlays(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<ListOverlaysResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        debug!(target: logging::FPGA, "list_overlays");
+        let overlays = self
+            .platform
+            .overlays()
+            .into_iter()
+            .map(|overlay| OverlayInfo {
+                name: overlay.name,
+                status: overlay.status,
+                path: overlay.path,
+            })
+            .collect();
+        let slots = self
+            .platform
+            .slots()
+            .into_iter()
+            .map(|(slot, accel)| SlotInfo { slot, accel })
+            .collect();
+        Ok(Response::new(ListOverlaysResponse {
+            result: true,
+            overlays,
+            slots,
         }))
     }
 
@@ -211,702 +978,1257 @@ impl JellyFpgaControl for JellyFpgaControlService {
         &self,
         request: Request<DtsToDtbRequest>,
     ) -> Result<Response<DtsToDtbResponse>, Status> {
+        auth::require(&request, Role::Operator)?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("dts_to_dtb");
-        }
-        let result = fpgautil::dtc_with_str(&req.dts);
-        match result {
-            Ok(dtb) => Ok(Response::new(DtsToDtbResponse {
-                result: true,
-                dtb: dtb,
-            })),
-            Err(e) => {
-                println!("Error:{}", e);
-                Ok(Response::new(DtsToDtbResponse {
-                    result: false,
-                    dtb: [].to_vec(),
-                }))
-            }
-        }
+        debug!(target: logging::FIRMWARE, "dts_to_dtb");
+        let dtb = self
+            .platform
+            .dts_to_dtb(&req.dts)
+            .map_err(|e| error::conversion_status("dts_to_dtb", e))?;
+        Ok(Response::new(DtsToDtbResponse { result: true, dtb }))
     }
 
     async fn bitstream_to_bin(
         &self,
         request: Request<BitstreamToBinRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.firmware, "firmware"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "bitstream_to_bin: bitstream_name={} bin_name={} arch={}",
-                req.bitstream_name, req.bin_name, req.arch
-            );
-        }
-        let bit_path = format!("/lib/firmware/{}", req.bitstream_name);
-        let bin_path = format!("/lib/firmware/{}", req.bin_name);
-        let result = fpgautil::xlnx_bitstream_to_bin(&bit_path, &bin_path, &req.arch);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(
+            target: logging::FIRMWARE,
+            bitstream_name = req.bitstream_name,
+            bin_name = req.bin_name,
+            arch = req.arch,
+            "bitstream_to_bin"
+        );
+        let result = (|| {
+            allowed?;
+            let bit_path = self.firmware_path("bitstream_name", &req.bitstream_name)?;
+            let bin_path = self.firmware_path("bin_name", &req.bin_name)?;
+            self.platform
+                .bitstream_to_bin(&bit_path, &bin_path, &req.arch)
+                .map_err(|e| error::conversion_status("bitstream_to_bin", e))
+        })();
+        let args = json!({
+            "bitstream_name": req.bitstream_name,
+            "bin_name": req.bin_name,
+            "arch": req.arch,
+        });
+        self.audit.record(&caller, "BitstreamToBin", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn load_remoteproc(
         &self,
         request: Request<LoadRemoteprocRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.remoteproc, "remoteproc"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "load_remoteproc: remoteproc_id={} elf_name={}",
-                req.remoteproc_id, req.elf_name,
-            );
-        }
-        let result = fpgautil::load_remoteproc_from_firmware(req.remoteproc_id as usize, &req.elf_name);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(
+            target: logging::REMOTEPROC,
+            remoteproc_id = req.remoteproc_id,
+            elf_name = req.elf_name,
+            "load_remoteproc"
+        );
+        let result = (|| {
+            allowed?;
+            let elf_name = self.stage_firmware("elf_name", &req.elf_name)?;
+            self.platform
+                .load_remoteproc(req.remoteproc_id as usize, &elf_name)
+                .map_err(|e| error::platform_status("load_remoteproc", e))
+        })();
+        let args = json!({ "remoteproc_id": req.remoteproc_id, "elf_name": req.elf_name });
+        self.audit.record(&caller, "LoadRemoteproc", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn start_remoteproc(
         &self,
         request: Request<RemoteprocIdRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.remoteproc, "remoteproc"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "start_remoteproc: remoteproc_id={}",
-                req.remoteproc_id,
-            );
-        }
-        let result = fpgautil::start_remoteproc(req.remoteproc_id as usize);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(target: logging::REMOTEPROC, remoteproc_id = req.remoteproc_id, "start_remoteproc");
+        let result = allowed.and_then(|()| {
+            self.platform
+                .start_remoteproc(req.remoteproc_id as usize)
+                .map_err(|e| error::platform_status("start_remoteproc", e))
+        });
+        let args = json!({ "remoteproc_id": req.remoteproc_id });
+        self.audit.record(&caller, "StartRemoteproc", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn stop_remoteproc(
         &self,
         request: Request<RemoteprocIdRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Admin)
+            .and_then(|()| require_feature(self.features.remoteproc, "remoteproc"));
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "stop_remoteproc: remoteproc_id={}",
-                req.remoteproc_id,
-            );
-        }
-        let result = fpgautil::stop_remoteproc(req.remoteproc_id as usize);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        debug!(target: logging::REMOTEPROC, remoteproc_id = req.remoteproc_id, "stop_remoteproc");
+        let result = allowed.and_then(|()| {
+            self.platform
+                .stop_remoteproc(req.remoteproc_id as usize)
+                .map_err(|e| error::platform_status("stop_remoteproc", e))
+        });
+        let args = json!({ "remoteproc_id": req.remoteproc_id });
+        self.audit.record(&caller, "StopRemoteproc", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn open_mmap(
         &self,
         request: Request<OpenMmapRequest>,
     ) -> Result<Response<OpenResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        require_feature(self.features.accessor, "accessor")?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("open_mmap: path={}", req.path);
+        debug!(target: logging::ACCESSOR, path = req.path, "open_mmap");
+        if !self.access.allows_mmap(&req.path, req.offset, req.size) {
+            return Err(error::not_allowed_status(
+                "mmap",
+                &format!("{} {:#x}+{:#x}", req.path, req.offset, req.size),
+            ));
         }
+        let offset = wire_usize("offset", req.offset)?;
+        let size = wire_usize("size", req.size)?;
         let mut accessor = self.accessor.write().await;
-        let result = accessor.open_mmap(
-            &req.path,
-            req.offset as usize,
-            req.size as usize,
-            req.unit as usize,
-        );
-        match result {
-            Ok(id) => Ok(Response::new(OpenResponse {
-                result: true,
-                id: id,
-            })),
-            Err(_) => Ok(Response::new(OpenResponse {
-                result: false,
-                id: 0,
-            })),
-        }
+        let id = accessor.open_mmap(&req.path, offset, size, req.unit as usize, session)?;
+        Ok(Response::new(OpenResponse {
+            result: true,
+            id,
+        }))
     }
 
     async fn open_uio(
         &self,
         request: Request<OpenUioRequest>,
     ) -> Result<Response<OpenResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        require_feature(self.features.accessor, "accessor")?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("open_uio: name={}", req.name);
+        debug!(target: logging::ACCESSOR, name = req.name, "open_uio");
+        if !self.access.allows_uio(&req.name) {
+            return Err(error::not_allowed_status("uio", &req.name));
         }
         let mut accessor = self.accessor.write().await;
-        let result = accessor.open_uio(&req.name, req.unit as usize);
-        match result {
-            Ok(id) => Ok(Response::new(OpenResponse {
-                result: true,
-                id: id,
-            })),
-            Err(_) => Ok(Response::new(OpenResponse {
-                result: false,
-                id: 0,
-            })),
-        }
+        let id = accessor.open_uio(&req.name, req.unit as usize, session)?;
+        Ok(Response::new(OpenResponse {
+            result: true,
+            id,
+        }))
     }
 
     async fn open_udmabuf(
         &self,
         request: Request<OpenUdmabufRequest>,
     ) -> Result<Response<OpenResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        require_feature(self.features.accessor, "accessor")?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("open_udmabuf: name={}", req.name);
+        debug!(target: logging::ACCESSOR, name = req.name, "open_udmabuf");
+        if !self.access.allows_udmabuf(&req.name) {
+            return Err(error::not_allowed_status("udmabuf", &req.name));
         }
         let mut accessor = self.accessor.write().await;
-        let result = accessor.open_udmabuf(&req.name, req.cache_enable, req.unit as usize);
-        match result {
-            Ok(id) => Ok(Response::new(OpenResponse {
-                result: true,
-                id: id,
-            })),
-            Err(_) => Ok(Response::new(OpenResponse {
-                result: false,
-                id: 0,
-            })),
-        }
+        let id = accessor.open_udmabuf(&req.name, req.cache_enable, req.unit as usize, session)?;
+        Ok(Response::new(OpenResponse {
+            result: true,
+            id,
+        }))
+    }
+
+    async fn open_sim(
+        &self,
+        request: Request<OpenSimRequest>,
+    ) -> Result<Response<OpenResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        require_feature(self.features.accessor, "accessor")?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(target: logging::ACCESSOR, name = req.name, size = req.size, "open_sim");
+        let size = wire_usize("size", req.size)?;
+        let phys_addr = wire_usize("phys_addr", req.phys_addr)?;
+        let mut accessor = self.accessor.write().await;
+        let id = accessor.open_sim(&req.name, size, phys_addr, req.unit as usize, session)?;
+        Ok(Response::new(OpenResponse {
+            result: true,
+            id,
+        }))
     }
 
     async fn subclone(
         &self,
         request: Request<SubcloneRequest>,
     ) -> Result<Response<SubcloneResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("subclone: id={} offset={} size={} unit={}", req.id, req.offset, req.size, req.unit);
-        }
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            size = req.size,
+            unit = req.unit,
+            "subclone"
+        );
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = accessor.subclone(req.id as accessor::Id, req.offset as usize, req.size as usize, req.unit as usize);
-        match result {
-            Ok(id) => Ok(Response::new(SubcloneResponse {
-                result: true,
-                id: id,
-            })),
-            Err(_) => Ok(Response::new(SubcloneResponse {
-                result: false,
-                id: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let (offset, size) = accessor.wire_range(req.id as accessor::Id, req.offset, req.size)?;
+        let id = accessor.subclone(
+            req.id as accessor::Id,
+            offset,
+            size,
+            req.unit as usize,
+            session,
+        )?;
+        Ok(Response::new(SubcloneResponse {
+            result: true,
+            id,
+        }))
     }
 
     async fn get_addr(
         &self,
         request: Request<GetAddrRequest>,
     ) -> Result<Response<GetAddrResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("get_addr: id={}", req.id);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, "get_addr");
+        logging::record_id(req.id);
         let accessor = self.accessor.read().await;
-        let result = accessor.addr(req.id as accessor::Id);
-        match result {
-            Ok(addr) => Ok(Response::new(GetAddrResponse {
-                result: true,
-                addr: addr as u64,
-            })),
-            Err(_) => Ok(Response::new(GetAddrResponse {
-                result: false,
-                addr: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let addr = accessor.addr(req.id as accessor::Id)?;
+        Ok(Response::new(GetAddrResponse {
+            result: true,
+            addr: addr as u64,
+        }))
     }
 
     async fn get_size(
         &self,
         request: Request<GetSizeRequest>,
     ) -> Result<Response<GetSizeResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("get_size: id={}", req.id);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, "get_size");
+        logging::record_id(req.id);
         let accessor = self.accessor.read().await;
-        let result = accessor.size(req.id as accessor::Id);
-        match result {
-            Ok(size) => Ok(Response::new(GetSizeResponse {
-                result: true,
-                size: size as u64,
-            })),
-            Err(_) => Ok(Response::new(GetSizeResponse {
-                result: false,
-                size: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let size = accessor.size(req.id as accessor::Id)?;
+        Ok(Response::new(GetSizeResponse {
+            result: true,
+            size: size as u64,
+        }))
     }
 
     async fn get_phys_addr(
         &self,
         request: Request<GetPhysAddrRequest>,
     ) -> Result<Response<GetPhysAddrResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("get_phys_addr: id={}", req.id);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, "get_phys_addr");
+        logging::record_id(req.id);
         let accessor = self.accessor.read().await;
-        let result = accessor.phys_addr(req.id as accessor::Id);
-        match result {
-            Ok(phys_addr) => Ok(Response::new(GetPhysAddrResponse {
-                result: true,
-                phys_addr: phys_addr as u64,
-            })),
-            Err(_) => Ok(Response::new(GetPhysAddrResponse {
-                result: false,
-                phys_addr: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let phys_addr = accessor.phys_addr(req.id as accessor::Id)?;
+        Ok(Response::new(GetPhysAddrResponse {
+            result: true,
+            phys_addr: phys_addr as u64,
+        }))
     }
 
     async fn close(
         &self,
         request: Request<CloseRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("close: id={}", req.id);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, "close");
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = accessor.close(req.id as accessor::Id);
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        accessor.close(req.id as accessor::Id, session)?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn write_mem_u(
         &self,
         request: Request<WriteMemURequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_mem_u: id={} offset={} data={} size={}",
-                req.id, req.offset, req.data, req.size
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            data = req.data,
+            size = req.size,
+            "write_mem_u"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let (offset, size) = accessor.wire_range(id, req.offset, req.size)?;
+            unsafe { accessor.write_mem_u(id, offset, req.data, size) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.write_mem_u(
-                req.id as accessor::Id,
-                req.offset as usize,
-                req.data,
-                req.size as usize,
-            )
-        };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({
+            "id": req.id,
+            "offset": req.offset,
+            "data": req.data,
+            "size": req.size,
+        });
+        self.audit.record_write(&caller, "WriteMemU", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn write_mem_i(
         &self,
         request: Request<WriteMemIRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_mem_i: id={} offset={} data={} size={}",
-                req.id, req.offset, req.data, req.size
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            data = req.data,
+            size = req.size,
+            "write_mem_i"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let (offset, size) = accessor.wire_range(id, req.offset, req.size)?;
+            unsafe { accessor.write_mem_i(id, offset, req.data, size) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.write_mem_i(
-                req.id as accessor::Id,
-                req.offset as usize,
-                req.data,
-                req.size as usize,
-            )
-        };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({
+            "id": req.id,
+            "offset": req.offset,
+            "data": req.data,
+            "size": req.size,
+        });
+        self.audit.record_write(&caller, "WriteMemI", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn read_mem_u(
         &self,
         request: Request<ReadMemRequest>,
     ) -> Result<Response<ReadUResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "read_mem_u: id={} offset={} size={}",
-                req.id, req.offset, req.size
-            );
-        }
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            size = req.size,
+            "read_mem_u"
+        );
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.read_mem_u(
-                req.id as accessor::Id,
-                req.offset as usize,
-                req.size as usize,
-            )
-        };
-        match result {
-            Ok(data) => Ok(Response::new(ReadUResponse {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadUResponse {
-                result: false,
-                data: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let (offset, size) = accessor.wire_range(req.id as accessor::Id, req.offset, req.size)?;
+        let data = unsafe {
+            accessor.read_mem_u(req.id as accessor::Id, offset, size)
+        }?;
+        Ok(Response::new(ReadUResponse {
+            result: true,
+            data,
+        }))
     }
 
     async fn read_mem_i(
         &self,
         request: Request<ReadMemRequest>,
     ) -> Result<Response<ReadIResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "read_mem_i: id={} offset={} size={}",
-                req.id, req.offset, req.size
-            );
-        }
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            size = req.size,
+            "read_mem_i"
+        );
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.read_mem_i(
-                req.id as accessor::Id,
-                req.offset as usize,
-                req.size as usize,
-            )
-        };
-        match result {
-            Ok(data) => Ok(Response::new(ReadIResponse {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadIResponse {
-                result: false,
-                data: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let (offset, size) = accessor.wire_range(req.id as accessor::Id, req.offset, req.size)?;
+        let data = unsafe {
+            accessor.read_mem_i(req.id as accessor::Id, offset, size)
+        }?;
+        Ok(Response::new(ReadIResponse {
+            result: true,
+            data,
+        }))
     }
 
     async fn write_reg_u(
         &self,
         request: Request<WriteRegURequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_reg_u: id={} reg={} data={} size={}",
-                req.id, req.reg, req.data, req.size
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            data = req.data,
+            size = req.size,
+            "write_reg_u"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let (reg, size) = accessor.wire_range(id, req.reg, req.size)?;
+            unsafe { accessor.write_reg_u(id, reg, req.data, size) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.write_reg_u(
-                req.id as accessor::Id,
-                req.reg as usize,
-                req.data,
-                req.size as usize,
-            )
-        };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "reg": req.reg, "data": req.data, "size": req.size });
+        self.audit.record_write(&caller, "WriteRegU", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn write_reg_i(
         &self,
         request: Request<WriteRegIRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_reg_i: id={} reg={} data={} size={}",
-                req.id, req.reg, req.data, req.size
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            data = req.data,
+            size = req.size,
+            "write_reg_i"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let (reg, size) = accessor.wire_range(id, req.reg, req.size)?;
+            unsafe { accessor.write_reg_i(id, reg, req.data, size) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.write_reg_i(
-                req.id as accessor::Id,
-                req.reg as usize,
-                req.data,
-                req.size as usize,
-            )
-        };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "reg": req.reg, "data": req.data, "size": req.size });
+        self.audit.record_write(&caller, "WriteRegI", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn read_reg_u(
         &self,
         request: Request<ReadRegRequest>,
     ) -> Result<Response<ReadUResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "read_reg_u: id={} reg={} size={}",
-                req.id, req.reg, req.size
-            );
-        }
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            size = req.size,
+            "read_reg_u"
+        );
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.read_reg_u(req.id as accessor::Id, req.reg as usize, req.size as usize)
-        };
-        match result {
-            Ok(data) => Ok(Response::new(ReadUResponse {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadUResponse {
-                result: false,
-                data: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let (reg, size) = accessor.wire_range(req.id as accessor::Id, req.reg, req.size)?;
+        let data = unsafe {
+            accessor.read_reg_u(req.id as accessor::Id, reg, size)
+        }?;
+        Ok(Response::new(ReadUResponse {
+            result: true,
+            data,
+        }))
     }
 
     async fn read_reg_i(
         &self,
         request: Request<ReadRegRequest>,
     ) -> Result<Response<ReadIResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "read_reg_i: id={} reg={} size={}",
-                req.id, req.reg, req.size
-            );
-        }
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            size = req.size,
+            "read_reg_i"
+        );
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.read_reg_i(req.id as accessor::Id, req.reg as usize, req.size as usize)
-        };
-        match result {
-            Ok(data) => Ok(Response::new(ReadIResponse {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadIResponse {
-                result: false,
-                data: 0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let (reg, size) = accessor.wire_range(req.id as accessor::Id, req.reg, req.size)?;
+        let data = unsafe {
+            accessor.read_reg_i(req.id as accessor::Id, reg, size)
+        }?;
+        Ok(Response::new(ReadIResponse {
+            result: true,
+            data,
+        }))
     }
 
     async fn write_mem_f32(
         &self,
         request: Request<WriteMemF32Request>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_mem_f32: id={} offset={} data={}",
-                req.id, req.offset, req.data
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            data = req.data,
+            "write_mem_f32"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let offset = accessor.wire_offset(id, req.offset)?;
+            unsafe { accessor.write_mem_f32(id, offset, req.data) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.write_mem_f32(req.id as accessor::Id, req.offset as usize, req.data)
-        };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "offset": req.offset, "data": req.data });
+        self.audit.record_write(&caller, "WriteMemF32", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn write_mem_f64(
         &self,
         request: Request<WriteMemF64Request>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_mem_f64: id={} offset={} data={}",
-                req.id, req.offset, req.data
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            data = req.data,
+            "write_mem_f64"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let offset = accessor.wire_offset(id, req.offset)?;
+            unsafe { accessor.write_mem_f64(id, offset, req.data) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.write_mem_f64(req.id as accessor::Id, req.offset as usize, req.data)
-        };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "offset": req.offset, "data": req.data });
+        self.audit.record_write(&caller, "WriteMemF64", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn read_mem_f32(
         &self,
         request: Request<ReadMemRequest>,
     ) -> Result<Response<ReadF32Response>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("read_mem_f32: id={} offset={}", req.id, req.offset);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, offset = req.offset, "read_mem_f32");
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe { accessor.read_mem_f32(req.id as accessor::Id, req.offset as usize) };
-        match result {
-            Ok(data) => Ok(Response::new(ReadF32Response {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadF32Response {
-                result: false,
-                data: 0.0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let offset = accessor.wire_offset(req.id as accessor::Id, req.offset)?;
+        let data = unsafe { accessor.read_mem_f32(req.id as accessor::Id, offset) }?;
+        Ok(Response::new(ReadF32Response {
+            result: true,
+            data,
+        }))
     }
 
     async fn read_mem_f64(
         &self,
         request: Request<ReadMemRequest>,
     ) -> Result<Response<ReadF64Response>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("read_mem_f64: id={} offset={}", req.id, req.offset);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, offset = req.offset, "read_mem_f64");
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe { accessor.read_mem_f64(req.id as accessor::Id, req.offset as usize) };
-        match result {
-            Ok(data) => Ok(Response::new(ReadF64Response {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadF64Response {
-                result: false,
-                data: 0.0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let offset = accessor.wire_offset(req.id as accessor::Id, req.offset)?;
+        let data = unsafe { accessor.read_mem_f64(req.id as accessor::Id, offset) }?;
+        Ok(Response::new(ReadF64Response {
+            result: true,
+            data,
+        }))
     }
 
     async fn write_reg_f32(
         &self,
         request: Request<WriteRegF32Request>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_reg_f32: id={} reg={} data={}",
-                req.id, req.reg, req.data
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            data = req.data,
+            "write_reg_f32"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let reg = accessor.wire_offset(id, req.reg)?;
+            unsafe { accessor.write_reg_f32(id, reg, req.data) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result =
-            unsafe { accessor.write_reg_f32(req.id as accessor::Id, req.reg as usize, req.data) };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "reg": req.reg, "data": req.data });
+        self.audit.record_write(&caller, "WriteRegF32", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn write_reg_f64(
         &self,
         request: Request<WriteRegF64Request>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "write_reg_f64: id={} reg={} data={}",
-                req.id, req.reg, req.data
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            data = req.data,
+            "write_reg_f64"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let reg = accessor.wire_offset(id, req.reg)?;
+            unsafe { accessor.write_reg_f64(id, reg, req.data) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result =
-            unsafe { accessor.write_reg_f64(req.id as accessor::Id, req.reg as usize, req.data) };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "reg": req.reg, "data": req.data });
+        self.audit.record_write(&caller, "WriteRegF64", args, &result);
+        result?;
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn read_reg_f32(
         &self,
         request: Request<ReadRegRequest>,
     ) -> Result<Response<ReadF32Response>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("read_reg_f32: id={} reg={}", req.id, req.reg);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, reg = req.reg, "read_reg_f32");
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe { accessor.read_reg_f32(req.id as accessor::Id, req.reg as usize) };
-        match result {
-            Ok(data) => Ok(Response::new(ReadF32Response {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadF32Response {
-                result: false,
-                data: 0.0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let reg = accessor.wire_offset(req.id as accessor::Id, req.reg)?;
+        let data = unsafe { accessor.read_reg_f32(req.id as accessor::Id, reg) }?;
+        Ok(Response::new(ReadF32Response {
+            result: true,
+            data,
+        }))
     }
 
     async fn read_reg_f64(
         &self,
         request: Request<ReadRegRequest>,
     ) -> Result<Response<ReadF64Response>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!("read_reg_f64: id={} reg={}", req.id, req.reg);
-        }
+        debug!(target: logging::ACCESSOR, id = req.id, reg = req.reg, "read_reg_f64");
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe { accessor.read_reg_f64(req.id as accessor::Id, req.reg as usize) };
-        match result {
-            Ok(data) => Ok(Response::new(ReadF64Response {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(ReadF64Response {
-                result: false,
-                data: 0.0,
-            })),
-        }
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let reg = accessor.wire_offset(req.id as accessor::Id, req.reg)?;
+        let data = unsafe { accessor.read_reg_f64(req.id as accessor::Id, reg) }?;
+        Ok(Response::new(ReadF64Response {
+            result: true,
+            data,
+        }))
     }
 
     async fn mem_copy_to(
         &self,
         request: Request<MemCopyToRequest>,
     ) -> Result<Response<BoolResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "mem_copy_to: id={} offset={}, len={}",
-                req.id,
-                req.offset,
-                req.data.len()
-            );
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            len = req.data.len(),
+            "mem_copy_to"
+        );
+        logging::record_id(req.id);
+        let result = async {
+            allowed?;
+            let session = session?;
+            let id = req.id as accessor::Id;
+            let mut accessor = self.accessor.write().await;
+            accessor.check_owner(id, session)?;
+            let offset = accessor.wire_offset(id, req.offset)?;
+            unsafe { accessor.mem_copy_to(id, offset, &req.data) }?;
+            Ok::<_, Status>(())
         }
-        let mut accessor = self.accessor.write().await;
-        let result =
-            unsafe { accessor.mem_copy_to(req.id as accessor::Id, req.offset as usize, &req.data) };
-        Ok(Response::new(BoolResponse {
-            result: result.is_ok(),
-        }))
+        .await;
+        let args = json!({ "id": req.id, "offset": req.offset, "len": req.data.len() });
+        self.audit.record_write(&caller, "MemCopyTo", args, &result);
+        result?;
+        metrics::add_mem_copy_to(req.data.len());
+        Ok(Response::new(BoolResponse { result: true }))
     }
 
     async fn mem_copy_from(
         &self,
         request: Request<MemCopyFromRequest>,
     ) -> Result<Response<MemCopyFromResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
         let req = request.into_inner();
-        if self.verbose >= 1 {
-            println!(
-                "mem_copy_from: id={} offset={} size={}",
-                req.id, req.offset, req.size
-            );
-        }
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            size = req.size,
+            "mem_copy_from"
+        );
+        logging::record_id(req.id);
         let mut accessor = self.accessor.write().await;
-        let result = unsafe {
-            accessor.mem_copy_from(
-                req.id as accessor::Id,
-                req.offset as usize,
-                req.size as usize,
-            )
+        accessor.check_owner(req.id as accessor::Id, session)?;
+        let (offset, size) = accessor.wire_range(req.id as accessor::Id, req.offset, req.size)?;
+        let data = unsafe { accessor.mem_copy_from(req.id as accessor::Id, offset, size) }?;
+        metrics::add_mem_copy_from(data.len());
+        Ok(Response::new(MemCopyFromResponse {
+            result: true,
+            data,
+        }))
+    }
+
+    async fn mem_write_stream(
+        &self,
+        request: Request<Streaming<MemWriteStreamRequest>>,
+    ) -> Result<Response<MemWriteStreamResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let allowed = auth::require(&request, Role::Operator);
+        let session = self.session(request.metadata()).await;
+        debug!(target: logging::ACCESSOR, "mem_write_stream");
+        let mut stream = request.into_inner();
+
+        let mut target = None;
+        let mut size = 0;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut expected_crc32 = None;
+        let result = async {
+            allowed?;
+            let session = session?;
+            while let Some(msg) = stream.next().await {
+                let msg = msg?;
+                trace!(target: logging::ACCESSOR, len = msg.data.len(), "mem_write_stream chunk");
+                let (id, offset) = *target.get_or_insert((msg.id as accessor::Id, msg.offset));
+                if !msg.data.is_empty() {
+                    let mut accessor = self.accessor.write().await;
+                    accessor.check_owner(id, session)?;
+                    let start = accessor.wire_offset(id, offset.saturating_add(size as u64))?;
+                    unsafe { accessor.mem_copy_to(id, start, &msg.data) }?;
+                    metrics::add_mem_copy_to(msg.data.len());
+                }
+                hasher.update(&msg.data);
+                size += msg.data.len();
+                if msg.has_crc32 {
+                    expected_crc32 = Some(msg.crc32);
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        let crc32 = hasher.finalize();
+        let (id, offset) = target.unwrap_or_default();
+        let args = json!({ "id": id, "offset": offset, "len": size, "crc32": crc32 });
+        self.audit.record_write(&caller, "MemWriteStream", args, &result);
+        result?;
+        debug!(target: logging::ACCESSOR, size, crc32, "mem_write_stream done");
+        if let Some(expected) = expected_crc32
+            && expected != crc32
+        {
+            return Err(error::checksum_status(
+                "crc32",
+                &format!("{:08x}", expected),
+                &format!("{:08x}", crc32),
+            ));
+        }
+        Ok(Response::new(MemWriteStreamResponse {
+            result: true,
+            size: size as u64,
+            crc32,
+        }))
+    }
+
+    type MemReadStreamStream = ReceiverStream<Result<MemReadStreamResponse, Status>>;
+
+    async fn mem_read_stream(
+        &self,
+        request: Request<MemReadStreamRequest>,
+    ) -> Result<Response<Self::MemReadStreamStream>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            size = req.size,
+            chunk_size = req.chunk_size,
+            "mem_read_stream"
+        );
+        logging::record_id(req.id);
+        let id = req.id as accessor::Id;
+        let (offset, size) = {
+            let accessor = self.accessor.read().await;
+            accessor.check_owner(id, session)?;
+            let (offset, size) = accessor.wire_range(id, req.offset, req.size)?;
+            // fail up front instead of in the middle of the stream
+            let mapped = accessor.size(id)?;
+            if offset.checked_add(size).is_none_or(|end| end > mapped) {
+                return Err(accessor::AccessorError::OutOfRange {
+                    id,
+                    offset: req.offset,
+                    len: req.size,
+                    size: mapped,
+                }
+                .into());
+            }
+            (offset, size)
+        };
+
+        let chunk_size = match req.chunk_size as usize {
+            0 => MEM_STREAM_DEFAULT_CHUNK,
+            n => n.min(MEM_STREAM_MAX_CHUNK),
         };
-        match result {
-            Ok(data) => Ok(Response::new(MemCopyFromResponse {
-                result: true,
-                data: data,
-            })),
-            Err(_) => Ok(Response::new(MemCopyFromResponse {
-                result: false,
-                data: [].to_vec(),
-            })),
+        let accessor = self.accessor.clone();
+        let (tx, rx) = mpsc::channel(MEM_STREAM_QUEUE);
+        tokio::spawn(async move {
+            let mut hasher = crc32fast::Hasher::new();
+            let mut pos = 0;
+            loop {
+                let len = chunk_size.min(size - pos);
+                let data = {
+                    let mut accessor = accessor.write().await;
+                    unsafe { accessor.mem_copy_from(id, offset + pos, len) }
+                };
+                let data = match data {
+                    Ok(data) => data,
+                    Err(e) => {
+                        let _ = tx.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                metrics::add_mem_copy_from(data.len());
+                hasher.update(&data);
+                let chunk_offset = (offset + pos) as u64;
+                pos += len;
+                let last = pos >= size;
+                let crc32 = if last && req.crc32 {
+                    hasher.clone().finalize()
+                } else {
+                    0
+                };
+                let msg = MemReadStreamResponse {
+                    offset: chunk_offset,
+                    data,
+                    last,
+                    crc32,
+                };
+                if tx.send(Ok(msg)).await.is_err() || last {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn set_irq_enable(
+        &self,
+        request: Request<SetIrqEnableRequest>,
+    ) -> Result<Response<BoolResponse>, Status> {
+        auth::require(&request, Role::Operator)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(target: logging::ACCESSOR, id = req.id, enable = req.enable, "set_irq_enable");
+        logging::record_id(req.id);
+        let irq = self.uio_irq(session, req.id as accessor::Id).await?;
+        irq.set_enable(req.enable)
+            .map_err(|e| error::platform_status("set_irq_enable", e.into()))?;
+        Ok(Response::new(BoolResponse { result: true }))
+    }
+
+    async fn wait_irq(
+        &self,
+        request: Request<WaitIrqRequest>,
+    ) -> Result<Response<WaitIrqResponse>, Status> {
+        auth::require(&request, Role::Operator)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            enable = req.enable,
+            timeout_ms = req.timeout_ms,
+            "wait_irq"
+        );
+        logging::record_id(req.id);
+        let irq = self.uio_irq(session, req.id as accessor::Id).await?;
+        if req.enable {
+            irq.set_enable(true)
+                .map_err(|e| error::platform_status("set_irq_enable", e.into()))?;
         }
+        let count = if req.timeout_ms == 0 {
+            Some(irq.wait().await)
+        } else {
+            tokio::time::timeout(Duration::from_millis(req.timeout_ms), irq.wait())
+                .await
+                .ok()
+        };
+        let count = count
+            .transpose()
+            .map_err(|e| error::platform_status("wait_irq", e.into()))?;
+        Ok(Response::new(WaitIrqResponse {
+            result: count.is_some(),
+            count: count.unwrap_or(0),
+            timed_out: count.is_none(),
+        }))
+    }
+
+    type SubscribeIrqStream = ReceiverStream<Result<IrqEvent, Status>>;
+
+    async fn subscribe_irq(
+        &self,
+        request: Request<SubscribeIrqRequest>,
+    ) -> Result<Response<Self::SubscribeIrqStream>, Status> {
+        auth::require(&request, Role::Operator)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            auto_enable = req.auto_enable,
+            "subscribe_irq"
+        );
+        logging::record_id(req.id);
+        let irq = self.uio_irq(session, req.id as accessor::Id).await?;
+        let (tx, rx) = mpsc::channel(IRQ_EVENT_QUEUE);
+        tokio::spawn(async move {
+            loop {
+                if req.auto_enable
+                    && let Err(e) = irq.set_enable(true)
+                {
+                    let _ = tx
+                        .send(Err(error::platform_status("set_irq_enable", e.into())))
+                        .await;
+                    break;
+                }
+                let count = tokio::select! {
+                    count = irq.wait() => count,
+                    _ = tx.closed() => break,
+                };
+                let event = match count {
+                    Ok(count) => Ok(IrqEvent {
+                        count,
+                        timestamp_us: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|t| t.as_micros() as u64)
+                            .unwrap_or(0),
+                    }),
+                    Err(e) => Err(error::platform_status("wait_irq", e.into())),
+                };
+                let failed = event.is_err();
+                if tx.send(event).await.is_err() || failed {
+                    break;
+                }
+            }
+            debug!(target: logging::ACCESSOR, "subscribe_irq end");
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn wait_reg(
+        &self,
+        request: Request<WaitRegRequest>,
+    ) -> Result<Response<WaitResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            reg = req.reg,
+            size = req.size,
+            mask = format_args!("{:#x}", req.mask),
+            expected = format_args!("{:#x}", req.expected),
+            condition = ?req.condition(),
+            timeout_ms = req.timeout_ms,
+            "wait_reg"
+        );
+        logging::record_id(req.id);
+        let op = accessor::BatchOp {
+            id: req.id as accessor::Id,
+            reg: true,
+            address: req.reg,
+            size: req.size,
+            kind: accessor::BatchKind::Read,
+        };
+        let spec = WaitSpec::new(
+            req.condition(),
+            req.mask,
+            req.expected,
+            req.interval_us,
+            req.timeout_ms,
+        );
+        let response = self.wait_value(session, op, spec).await?;
+        Ok(Response::new(response))
+    }
+
+    async fn wait_mem(
+        &self,
+        request: Request<WaitMemRequest>,
+    ) -> Result<Response<WaitResponse>, Status> {
+        auth::require(&request, Role::ReadOnly)?;
+        let session = self.session(request.metadata()).await?;
+        let req = request.into_inner();
+        debug!(
+            target: logging::ACCESSOR,
+            id = req.id,
+            offset = req.offset,
+            size = req.size,
+            mask = format_args!("{:#x}", req.mask),
+            expected = format_args!("{:#x}", req.expected),
+            condition = ?req.condition(),
+            timeout_ms = req.timeout_ms,
+            "wait_mem"
+        );
+        logging::record_id(req.id);
+        let op = accessor::BatchOp {
+            id: req.id as accessor::Id,
+            reg: false,
+            address: req.offset,
+            size: req.size,
+            kind: accessor::BatchKind::Read,
+        };
+        let spec = WaitSpec::new(
+            req.condition(),
+            req.mask,
+            req.expected,
+            req.interval_us,
+            req.timeout_ms,
+        );
+        let response = self.wait_value(session, op, spec).await?;
+        Ok(Response::new(response))
+    }
+
+    async fn execute_batch(
+        &self,
+        request: Request<ExecuteBatchRequest>,
+    ) -> Result<Response<ExecuteBatchResponse>, Status> {
+        let caller = audit::Caller::new(&request);
+        let role = auth::role(&request);
+        let session = self.session(request.metadata()).await;
+        let req = request.into_inner();
+        debug!(
+            target: logging::ACCESSOR,
+            ops = req.ops.len(),
+            stop_on_error = req.stop_on_error,
+            "execute_batch"
+        );
+        let ops: Vec<accessor::BatchOp> = req.ops.iter().map(batch_op).collect();
+        let writes = ops.iter().filter(|op| op.kind != accessor::BatchKind::Read).count();
+        let audit = |executed: usize, status: &Result<(), Status>| {
+            if writes > 0 {
+                let args = json!({ "ops": ops.len(), "writes": writes, "executed": executed });
+                self.audit.record_write(&caller, "ExecuteBatch", args, status);
+            }
+        };
+        // a batch of reads only is allowed for read-only tokens
+        let allowed = match writes {
+            0 => Ok(()),
+            _ => auth::check(role, Role::Operator),
+        };
+        let session = match allowed.and(session) {
+            Ok(session) => session,
+            Err(status) => {
+                audit(0, &Err(status.clone()));
+                return Err(status);
+            }
+        };
+        let results = {
+            let mut accessor = self.accessor.write().await;
+            unsafe { accessor.execute_batch(session, &ops, req.stop_on_error) }
+        };
+        let results: Vec<BatchOpResult> = results.into_iter().map(batch_result).collect();
+        let result = results.len() == ops.len() && results.iter().all(|r| r.result);
+        let status = match results.iter().find(|r| !r.result) {
+            Some(failed) => batch_status(failed),
+            None => Ok(()),
+        };
+        audit(results.len(), &status);
+        Ok(Response::new(ExecuteBatchResponse { result, results }))
+    }
+
+    type RegisterSessionStream = ReceiverStream<Result<RegisterResult, Status>>;
+
+    async fn register_session(
+        &self,
+        request: Request<Streaming<RegisterCommand>>,
+    ) -> Result<Response<Self::RegisterSessionStream>, Status> {
+        let role = auth::role(&request);
+        let session = self.session(request.metadata()).await?;
+        debug!(target: logging::ACCESSOR, "register_session start");
+        let caller = audit::Caller::new(&request);
+        let mut stream = request.into_inner();
+        let accessor = self.accessor.clone();
+        let audit = self.audit.clone();
+        let (tx, rx) = mpsc::channel(REGISTER_SESSION_QUEUE);
+        tokio::spawn(async move {
+            while let Some(cmd) = stream.next().await {
+                let cmd = match cmd {
+                    Ok(cmd) => cmd,
+                    Err(e) => {
+                        let _ = tx.send(Err(e)).await;
+                        break;
+                    }
+                };
+                let result = match &cmd.op {
+                    Some(op) => {
+                        let op = batch_op(op);
+                        // like ExecuteBatch, read-only tokens may send reads
+                        let allowed = match op.kind {
+                            accessor::BatchKind::Read => Ok(()),
+                            _ => auth::check(role, Role::Operator),
+                        };
+                        let result = match allowed {
+                            Ok(()) => {
+                                let mut accessor = accessor.write().await;
+                                batch_result(unsafe { accessor.execute(session, &op) })
+                            }
+                            Err(status) => status_result(&status),
+                        };
+                        if op.kind != accessor::BatchKind::Read {
+                            let args = batch_args(&op);
+                            let status = batch_status(&result);
+                            audit.record_write(&caller, "RegisterSession", args, &status);
+                        }
+                        result
+                    }
+                    None => BatchOpResult {
+                        result: false,
+                        data: 0,
+                        code: tonic::Code::InvalidArgument as i32,
+                        message: "missing op".to_string(),
+                    },
+                };
+                let result = RegisterResult {
+                    seq: cmd.seq,
+                    result: result.result,
+                    data: result.data,
+                    code: result.code,
+                    message: result.message,
+                };
+                if tx.send(Ok(result)).await.is_err() {
+                    break;
+                }
+            }
+            debug!(target: logging::ACCESSOR, "register_session end");
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 }
 
@@ -915,54 +2237,221 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Verbose level
-    #[arg(short, long, default_value_t = 0)]
-    verbose: i32,
+    /// Configuration file [default: /etc/jelly-fpga-server/config.toml]
+    #[arg(short, long)]
+    config: Option<String>,
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    print_config: bool,
+    /// Verbose level, used as the log level unless one is configured [default: 0]
+    #[arg(short, long)]
+    verbose: Option<i32>,
+    /// Log level filter, e.g. "info" or "info,firmware=debug"
+    #[arg(long)]
+    log_level: Option<String>,
+    /// Log output format [default: text]
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
     /// Allow external connections
     #[arg(long)]
     external: bool,
-    /// Port number to listen on
-    #[arg(short, long, default_value_t = 8051)]
-    port: u16,
+    /// Port number to listen on [default: 8051]
+    #[arg(short, long)]
+    port: Option<u16>,
     /// IP address to bind to (overrides external flag). Example: 192.168.1.10 or 127.0.0.1
     #[arg(long)]
     bind: Option<String>,
     #[arg(long)]
     allow_sudo: bool,
+    /// PEM server certificate; enables TLS together with --tls-key
+    #[arg(long)]
+    tls_cert: Option<String>,
+    /// PEM private key of the server certificate
+    #[arg(long)]
+    tls_key: Option<String>,
+    /// PEM CA certificate; only clients with a certificate signed by it can connect (mTLS)
+    #[arg(long)]
+    tls_client_ca: Option<String>,
+    /// File of "<role> <token> [name]" lines (read-only, operator, admin); enables authorization
+    #[arg(long)]
+    token_file: Option<String>,
+    /// Append state-changing operations to this JSON lines file
+    #[arg(long)]
+    audit_log: Option<String>,
+    /// Also record register and memory writes in the audit log
+    #[arg(long)]
+    audit_writes: bool,
+    /// Address of the Prometheus metrics endpoint. Example: 0.0.0.0:9100
+    #[arg(long)]
+    metrics_listen: Option<String>,
+    /// Directory firmware is uploaded to and loaded from [default: /lib/firmware]
+    #[arg(long)]
+    firmware_dir: Option<String>,
+    /// How firmware outside /lib/firmware reaches the kernel loader [default: copy]
+    #[arg(long, value_enum)]
+    firmware_staging: Option<FirmwareStaging>,
+    /// Platform backend; sim runs without a board [default: fpgautil]
+    #[arg(long, value_enum)]
+    backend: Option<Backend>,
+    /// Directory of the simulated platform
+    #[arg(long)]
+    sim_dir: Option<String>,
+}
+
+// command line flags take precedence over the configuration file
+fn merge_args(config: &mut Config, args: &Args) {
+    if let Some(verbose) = args.verbose {
+        config.verbose = verbose;
+    }
+    if args.log_level.is_some() {
+        config.log.level = args.log_level.clone();
+    }
+    if let Some(format) = args.log_format {
+        config.log.format = format;
+    }
+    if args.external {
+        config.server.external = true;
+    }
+    if let Some(port) = args.port {
+        config.server.port = port;
+    }
+    if args.bind.is_some() {
+        config.server.bind = args.bind.clone();
+    }
+    if args.audit_log.is_some() {
+        config.audit.path = args.audit_log.clone();
+    }
+    if args.audit_writes {
+        config.audit.writes = true;
+    }
+    if args.metrics_listen.is_some() {
+        config.metrics.listen = args.metrics_listen.clone();
+    }
+    if let Some(dir) = &args.firmware_dir {
+        config.firmware.dir = dir.clone();
+    }
+    if let Some(staging) = args.firmware_staging {
+        config.firmware.staging = staging;
+    }
+    if let Some(backend) = args.backend {
+        config.platform.backend = backend;
+    }
+    if args.sim_dir.is_some() {
+        config.platform.sim_dir = args.sim_dir.clone();
+    }
+    let security = &mut config.security;
+    if args.allow_sudo {
+        security.allow_sudo = true;
+    }
+    if args.tls_cert.is_some() {
+        security.tls_cert = args.tls_cert.clone();
+    }
+    if args.tls_key.is_some() {
+        security.tls_key = args.tls_key.clone();
+    }
+    if args.tls_client_ca.is_some() {
+        security.tls_client_ca = args.tls_client_ca.clone();
+    }
+    if args.token_file.is_some() {
+        security.token_file = args.token_file.clone();
+    }
+}
+
+fn tls_config(
+    security: &SecurityConfig,
+) -> Result<Option<ServerTlsConfig>, Box<dyn std::error::Error>> {
+    let (Some(cert), Some(key)) = (&security.tls_cert, &security.tls_key) else {
+        return Ok(None);
+    };
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))
+    };
+    let mut config = ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?));
+    if let Some(ca) = &security.tls_client_ca {
+        config = config.client_ca_root(Certificate::from_pem(read(ca)?));
+    }
+    Ok(Some(config))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    if args.allow_sudo {
-        fpgautil::set_allow_sudo(true);
+    let mut config = match &args.config {
+        Some(path) => Config::load(path, true)?,
+        None => Config::load(config::DEFAULT_CONFIG_PATH, false)?,
+    };
+    merge_args(&mut config, &args);
+    config.validate()?;
+
+    if args.print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
     }
 
-    let fpga_control_service = JellyFpgaControlService::new(args.verbose);
+    logging::init(&config.log, config.verbose)?;
 
-    let address = if let Some(bind_ip) = &args.bind {
-        format!("{}:{}", bind_ip, args.port)
-    } else if args.external {
-        format!("0.0.0.0:{}", args.port)
-    } else {
-        format!("127.0.0.1:{}", args.port)
+    let platform = platform::open(&config)?;
+    // the default firmware directory follows the backend, into sim_dir for the simulation
+    if config.firmware.dir == config::DEFAULT_FIRMWARE_DIR {
+        let dir = platform.system_path(platform::KERNEL_FIRMWARE_DIR);
+        config.firmware.dir = dir.to_string_lossy().into_owned();
     }
-    .parse()
-    .unwrap();
 
-    if args.verbose >= 1 {
-        println!("jelly-fpga-server start");
+    let audit = AuditLog::open(&config.audit)?;
+    if let Some(path) = &config.audit.path {
+        info!(target: logging::SERVER, path, writes = config.audit.writes, "audit log enabled");
     }
+    let firmware_root = FirmwareRoot::new(&config.firmware.dir);
+    let stager = Stager::new(&firmware_root, &config.firmware, platform.as_ref())?;
+    let fpga_control_service =
+        JellyFpgaControlService::new(&config, platform.clone(), stager, audit);
+    fpga_control_service.spawn_session_expiry();
+    if let Some(listen) = &config.metrics.listen {
+        let accessor = fpga_control_service.accessor.clone();
+        metrics::serve(listen.parse()?, accessor, platform.clone()).await?;
+    }
+
+    let address = config.server.address().parse()?;
 
-    Server::builder()
-        .add_service(JellyFpgaControlServer::new(fpga_control_service))
+    let security = &config.security;
+    let tls = tls_config(security)?;
+    let tokens = security.token_file.as_deref().map(auth::load_tokens).transpose()?;
+    if let Some(tokens) = &tokens {
+        info!(target: logging::SERVER, tokens = tokens.len(), "token authorization enabled");
+    }
+    if tls.is_none() && config.server.external {
+        warn!(target: logging::SERVER, "accepting external connections without TLS");
+    }
+
+    info!(
+        target: logging::SERVER,
+        tls = tls.is_some(),
+        mtls = security.tls_client_ca.is_some(),
+        "jelly-fpga-server start"
+    );
+
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    spawn_health_check(health_reporter, platform);
+    let reflection_service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(jelly_fpga_control::FILE_DESCRIPTOR_SET)
+        .build_v1()?;
+
+    let mut builder = Server::builder().layer(RequestLogLayer);
+    if let Some(tls) = tls {
+        builder = builder.tls_config(tls)?;
+    }
+    builder
+        .add_service(health_service)
+        .add_service(reflection_service)
+        .add_service(JellyFpgaControlServer::with_interceptor(
+            fpga_control_service,
+            AuthInterceptor::new(tokens),
+        ))
         .serve(address)
         .await?;
 
-    if args.verbose >= 1 {
-        println!("jelly-fpga-server stop");
-    }
+    info!(target: logging::SERVER, "jelly-fpga-server stop");
 
     Ok(())
 }
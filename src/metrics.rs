@@ -0,0 +1,289 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{RwLock, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::accessor::Accessor;
+use crate::logging;
+use crate::platform::PlatformBackend;
+
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_CONNECTIONS: usize = 16;
+
+static REGISTRY: Lazy<Registry> =
+    Lazy::new(|| Registry::new_custom(Some("jelly".into()), None).unwrap());
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(collector: T) -> T {
+    REGISTRY.register(Box::new(collector.clone())).unwrap();
+    collector
+}
+
+static RPC_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "rpc_requests_total",
+                "gRPC requests by method and status code",
+            ),
+            &["method", "code"],
+        )
+        .unwrap(),
+    )
+});
+
+static RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "rpc_duration_seconds",
+                "gRPC request latency until the response headers",
+            ),
+            &["method"],
+        )
+        .unwrap(),
+    )
+});
+
+static MEM_COPY_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("mem_copy_bytes_total", "bytes copied to and from accessors"),
+            &["direction"],
+        )
+        .unwrap(),
+    )
+});
+
+static FIRMWARE_UPLOAD_BYTES: Lazy<IntCounter> = Lazy::new(|| {
+    register(
+        IntCounter::new(
+            "firmware_upload_bytes_total",
+            "bytes written by UploadFirmware",
+        )
+        .unwrap(),
+    )
+});
+
+static BITSTREAM_LOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "bitstream_loads_total",
+                "bitstream and overlay loads by RPC and result",
+            ),
+            &["method", "result"],
+        )
+        .unwrap(),
+    )
+});
+
+static BITSTREAM_LOAD_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "bitstream_load_duration_seconds",
+                "time spent loading bitstreams",
+            )
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+            &["method"],
+        )
+        .unwrap(),
+    )
+});
+
+// refreshed on every scrape
+static ACCESSOR_HANDLES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new("accessor_handles", "open accessor handles by kind"),
+            &["kind"],
+        )
+        .unwrap(),
+    )
+});
+
+static FPGA_MANAGER_STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "fpga_manager_state",
+                "1 for the current state of each FPGA manager",
+            ),
+            &["manager", "state"],
+        )
+        .unwrap(),
+    )
+});
+
+// metrics register on first use, so a scrape before any traffic would miss them
+fn register_all() {
+    Lazy::force(&RPC_REQUESTS);
+    Lazy::force(&RPC_DURATION);
+    Lazy::force(&MEM_COPY_BYTES);
+    Lazy::force(&FIRMWARE_UPLOAD_BYTES);
+    Lazy::force(&BITSTREAM_LOADS);
+    Lazy::force(&BITSTREAM_LOAD_DURATION);
+    Lazy::force(&ACCESSOR_HANDLES);
+    Lazy::force(&FPGA_MANAGER_STATE);
+}
+
+pub fn observe_rpc(method: &str, code: tonic::Code, elapsed: Duration) {
+    RPC_REQUESTS
+        .with_label_values(&[method, &format!("{:?}", code)])
+        .inc();
+    RPC_DURATION
+        .with_label_values(&[method])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn add_mem_copy_to(bytes: usize) {
+    MEM_COPY_BYTES
+        .with_label_values(&["to"])
+        .inc_by(bytes as u64);
+}
+
+pub fn add_mem_copy_from(bytes: usize) {
+    MEM_COPY_BYTES
+        .with_label_values(&["from"])
+        .inc_by(bytes as u64);
+}
+
+pub fn add_firmware_upload(bytes: u64) {
+    FIRMWARE_UPLOAD_BYTES.inc_by(bytes);
+}
+
+pub fn observe_bitstream_load(method: &str, ok: bool, elapsed: Duration) {
+    let result = if ok { "ok" } else { "error" };
+    BITSTREAM_LOADS.with_label_values(&[method, result]).inc();
+    BITSTREAM_LOAD_DURATION
+        .with_label_values(&[method])
+        .observe(elapsed.as_secs_f64());
+}
+
+fn update_fpga_manager_state(platform: &dyn PlatformBackend) {
+    FPGA_MANAGER_STATE.reset();
+    for manager in platform.fpga_managers() {
+        if manager.state.is_empty() {
+            continue;
+        }
+        FPGA_MANAGER_STATE
+            .with_label_values(&[&manager.name, &manager.state])
+            .set(1);
+    }
+}
+
+async fn render(accessor: &RwLock<Accessor>, platform: &dyn PlatformBackend) -> Vec<u8> {
+    ACCESSOR_HANDLES.reset();
+    for (kind, count) in accessor.read().await.count_by_kind() {
+        ACCESSOR_HANDLES
+            .with_label_values(&[kind])
+            .set(count as i64);
+    }
+    update_fpga_manager_state(platform);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        warn!(target: logging::SERVER, error = %e, "failed to encode metrics");
+    }
+    buffer
+}
+
+// None when the connection closed or the head is too large
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || head.len() + n > MAX_REQUEST_HEAD {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(Some(head))
+}
+
+// Just enough HTTP/1.1 for a scraper: GET /metrics, one response per connection.
+async fn handle(
+    mut stream: TcpStream,
+    accessor: Arc<RwLock<Accessor>>,
+    platform: Arc<dyn PlatformBackend>,
+) -> std::io::Result<()> {
+    let head = tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request head timed out"))?;
+    let Some(head) = head? else {
+        return Ok(());
+    };
+    let request_line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+    let path = path.map(|p| p.split(|&b| b == b'?').next().unwrap_or_default());
+
+    let (status, content_type, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => (
+            "200 OK",
+            TextEncoder::new().format_type().to_string(),
+            render(&accessor, platform.as_ref()).await,
+        ),
+        _ => (
+            "404 Not Found",
+            "text/plain".to_string(),
+            b"not found\n".to_vec(),
+        ),
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await
+}
+
+pub async fn serve(
+    address: SocketAddr,
+    accessor: Arc<RwLock<Accessor>>,
+    platform: Arc<dyn PlatformBackend>,
+) -> std::io::Result<()> {
+    register_all();
+    let listener = TcpListener::bind(address).await?;
+    info!(target: logging::SERVER, %address, "metrics endpoint listening");
+    let connections = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!(target: logging::SERVER, error = %e, "metrics accept failed");
+                    continue;
+                }
+            };
+            // scrapers retry, so excess connections are just closed
+            let Ok(permit) = connections.clone().try_acquire_owned() else {
+                debug!(target: logging::SERVER, %peer, "metrics connection limit reached");
+                continue;
+            };
+            let accessor = accessor.clone();
+            let platform = platform.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle(stream, accessor, platform).await {
+                    debug!(target: logging::SERVER, %peer, error = %e, "metrics request failed");
+                }
+                drop(permit);
+            });
+        }
+    });
+    Ok(())
+}
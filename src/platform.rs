@@ -0,0 +1,178 @@
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{Backend, Config};
+
+pub mod fpgautil;
+use fpgautil::FpgautilBackend;
+
+pub mod sim;
+use sim::SimBackend;
+
+const SIM_DIR: &str = "jelly-fpga-server-sim";
+// slots outlive a restart of the server but not a reboot, like the overlays
+const SLOTS_FILE: &str = "/run/jelly-fpga-server/slots.json";
+
+// system locations, relative to the backend root
+pub const KERNEL_FIRMWARE_DIR: &str = "/lib/firmware";
+pub const FPGA_MANAGER_CLASS: &str = "/sys/class/fpga_manager";
+pub const REMOTEPROC_CLASS: &str = "/sys/class/remoteproc";
+pub const OVERLAY_DIR: &str = "/sys/kernel/config/device-tree/overlays";
+pub const FIRMWARE_CLASS_PATH: &str = "/sys/module/firmware_class/parameters/path";
+
+// dfx-mgr looks for accelerators here, below the firmware directory
+pub const ACCEL_DIR: &str = "xilinx";
+
+/// The manager bitstreams are loaded into by jelly-fpgautil.
+pub const DEFAULT_FPGA_MANAGER: &str = "fpga0";
+
+pub type PlatformResult<T> = Result<T, Box<dyn Error>>;
+
+/// An FPGA manager found under the fpga_manager class. Attributes the
+/// driver does not expose are left empty.
+#[derive(Debug, Clone)]
+pub struct FpgaManager {
+    pub name: String,
+    /// driver description from the `name` attribute
+    pub description: String,
+    pub state: String,
+    /// hex value of the Xilinx `flags` attribute
+    pub flags: String,
+    /// error bits set in `status`, one line each
+    pub status: Vec<String>,
+}
+
+/// A device tree overlay in the configfs overlay directory.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    pub name: String,
+    /// applied or unapplied
+    pub status: String,
+    /// dtbo it was applied from, relative to the kernel firmware directory;
+    /// empty when the blob was written directly
+    pub path: String,
+}
+
+fn read_attr(dir: &Path, attr: &str) -> String {
+    std::fs::read_to_string(dir.join(attr))
+        .map(|value| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Everything the server does to the board besides memory access. Names of
+/// bitstreams, overlays and remoteproc firmware are relative to the kernel
+/// firmware directory, other paths are absolute.
+pub trait PlatformBackend: fmt::Debug + Send + Sync {
+    /// Directory the system paths above are located in, "/" on a real board.
+    fn root(&self) -> &Path;
+
+    fn system_path(&self, path: &str) -> PathBuf {
+        self.root().join(path.trim_start_matches('/'))
+    }
+
+    // bitstreams and overlays
+    fn load(&self, accel_name: &str) -> PlatformResult<i32>;
+    fn unload(&self, slot: i32) -> PlatformResult<()>;
+    /// Accelerators loaded with `load` and not unloaded since, by slot.
+    fn slots(&self) -> Vec<(i32, String)>;
+    fn load_bitstream(&self, name: &str) -> PlatformResult<()>;
+    fn load_dtbo(&self, name: &str) -> PlatformResult<()>;
+
+    // accelerators
+    fn register_accel(
+        &self,
+        accel_name: &str,
+        bin_file: &str,
+        dtbo_file: &str,
+        json_file: Option<&str>,
+        overwrite: bool,
+    ) -> PlatformResult<()>;
+    fn unregister_accel(&self, accel_name: &str) -> PlatformResult<()>;
+
+    // firmware storage, the backend decides whether to raise privileges
+    fn write_file(&self, path: &str, data: &[u8]) -> PlatformResult<()>;
+    fn append_file(&self, path: &str, data: &[u8]) -> PlatformResult<()>;
+    fn remove_file(&self, path: &Path) -> PlatformResult<()>;
+    fn rename_file(&self, from: &Path, to: &Path) -> PlatformResult<()>;
+    fn copy_file(&self, from: &Path, to: &Path) -> PlatformResult<()>;
+    fn create_dir(&self, path: &Path) -> PlatformResult<()>;
+
+    // conversions
+    fn dts_to_dtb(&self, dts: &str) -> PlatformResult<Vec<u8>>;
+    fn bitstream_to_bin(&self, bit_file: &str, bin_file: &str, arch: &str) -> PlatformResult<()>;
+
+    // remoteproc
+    fn load_remoteproc(&self, id: usize, name: &str) -> PlatformResult<()>;
+    fn start_remoteproc(&self, id: usize) -> PlatformResult<()>;
+    fn stop_remoteproc(&self, id: usize) -> PlatformResult<()>;
+
+    // device discovery
+    fn fpga_managers(&self) -> Vec<FpgaManager> {
+        let Ok(entries) = std::fs::read_dir(self.system_path(FPGA_MANAGER_CLASS)) else {
+            return Vec::new();
+        };
+        let mut managers: Vec<FpgaManager> = entries
+            .flatten()
+            .map(|entry| {
+                let dir = entry.path();
+                FpgaManager {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    description: read_attr(&dir, "name"),
+                    state: read_attr(&dir, "state"),
+                    flags: read_attr(&dir, "flags"),
+                    status: read_attr(&dir, "status")
+                        .lines()
+                        .map(|line| line.trim().to_string())
+                        .filter(|line| !line.is_empty())
+                        .collect(),
+                }
+            })
+            .collect();
+        managers.sort_by(|a, b| a.name.cmp(&b.name));
+        managers
+    }
+
+    fn has_fpga_manager(&self) -> bool {
+        !self.fpga_managers().is_empty()
+    }
+
+    fn overlays(&self) -> Vec<Overlay> {
+        let Ok(entries) = std::fs::read_dir(self.system_path(OVERLAY_DIR)) else {
+            return Vec::new();
+        };
+        let mut overlays: Vec<Overlay> = entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| {
+                let dir = entry.path();
+                Overlay {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    status: read_attr(&dir, "status"),
+                    path: read_attr(&dir, "path"),
+                }
+            })
+            .collect();
+        overlays.sort_by(|a, b| a.name.cmp(&b.name));
+        overlays
+    }
+}
+
+pub fn open(config: &Config) -> Result<Arc<dyn PlatformBackend>, Box<dyn Error>> {
+    Ok(match config.platform.backend {
+        Backend::Fpgautil => {
+            let slots_file = config.platform.slots_file.as_deref().unwrap_or(SLOTS_FILE);
+            Arc::new(FpgautilBackend::new(config.security.allow_sudo, Path::new(slots_file)))
+        }
+        Backend::Sim => {
+            let dir = match &config.platform.sim_dir {
+                Some(dir) => PathBuf::from(dir),
+                None => std::env::temp_dir().join(SIM_DIR),
+            };
+            let sim = SimBackend::new(&dir)
+                .map_err(|e| format!("failed to set up {}: {}", dir.display(), e))?;
+            Arc::new(sim)
+        }
+    })
+}
@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use jelly_fpgautil as fpgautil;
+use jelly_uidmng as uidmng;
+use tracing::{info, warn};
+
+use super::{ACCEL_DIR, Overlay, PlatformBackend, PlatformResult};
+use crate::logging;
+
+type Slots = BTreeMap<i32, String>;
+
+fn sudo(args: &[&OsStr]) -> PlatformResult<()> {
+    let status = Command::new("sudo").arg("-n").args(args).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("sudo {:?} failed: {}", args, status),
+        )
+        .into())
+    }
+}
+
+// an accelerator is loaded while an overlay from its directory is applied
+fn overlay_loaded(overlays: &[Overlay], accel_name: &str) -> bool {
+    let dir = format!("{}/{}/", ACCEL_DIR, accel_name);
+    overlays.iter().any(|overlay| {
+        let path = overlay.path.trim_start_matches('/');
+        overlay.status == "applied" && (overlay.name == accel_name || path.starts_with(&dir))
+    })
+}
+
+// drops slots unloaded behind the server's back, e.g. by fpgautil on the shell
+fn reconcile(slots: Slots, overlays: &[Overlay]) -> Slots {
+    slots
+        .into_iter()
+        .filter(|(_, accel_name)| overlay_loaded(overlays, accel_name))
+        .collect()
+}
+
+fn read_slots(path: &Path) -> Slots {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+fn write_slots(path: &Path, slots: &Slots) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_vec(slots)?)
+}
+
+/// The real board, driven through jelly-fpgautil and jelly-uidmng.
+#[derive(Debug)]
+pub struct FpgautilBackend {
+    // slot -> accelerator, for loads made with `load`, kept in slots_file
+    slots: Mutex<Slots>,
+    slots_file: PathBuf,
+    allow_sudo: bool,
+}
+
+impl FpgautilBackend {
+    pub fn new(allow_sudo: bool, slots_file: &Path) -> Self {
+        if allow_sudo {
+            fpgautil::set_allow_sudo(true);
+        }
+        let backend = FpgautilBackend {
+            slots: Mutex::new(Slots::new()),
+            slots_file: slots_file.to_path_buf(),
+            allow_sudo,
+        };
+        let slots = reconcile(read_slots(slots_file), &backend.overlays());
+        if !slots.is_empty() {
+            info!(target: logging::FPGA, ?slots, "slots restored");
+        }
+        backend.store_slots(&slots);
+        *backend.slots.lock().unwrap_or_else(|e| e.into_inner()) = slots;
+        backend
+    }
+
+    fn store_slots(&self, slots: &Slots) {
+        if let Err(e) = write_slots(&self.slots_file, slots) {
+            let path = self.slots_file.display();
+            warn!(target: logging::FPGA, %path, error = %e, "failed to store slots");
+        }
+    }
+
+    // the firmware directory is usually owned by root, like uidmng retry the
+    // command with sudo, but only when the configuration allows it
+    fn or_sudo(&self, result: io::Result<()>, args: &[&OsStr]) -> PlatformResult<()> {
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && self.allow_sudo => sudo(args),
+            result => Ok(result?),
+        }
+    }
+}
+
+impl PlatformBackend for FpgautilBackend {
+    fn root(&self) -> &Path {
+        Path::new("/")
+    }
+
+    fn load(&self, accel_name: &str) -> PlatformResult<i32> {
+        let slot = fpgautil::load(accel_name)?;
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.insert(slot, accel_name.to_string());
+        self.store_slots(&slots);
+        Ok(slot)
+    }
+
+    fn unload(&self, slot: i32) -> PlatformResult<()> {
+        fpgautil::unload(slot)?;
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.remove(&slot);
+        self.store_slots(&slots);
+        Ok(())
+    }
+
+    fn slots(&self) -> Vec<(i32, String)> {
+        let slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.iter().map(|(slot, accel)| (*slot, accel.clone())).collect()
+    }
+
+    fn load_bitstream(&self, name: &str) -> PlatformResult<()> {
+        fpgautil::load_bitstream_from_firmware(name)
+    }
+
+    fn load_dtbo(&self, name: &str) -> PlatformResult<()> {
+        fpgautil::load_dtbo_from_firmware(name)
+    }
+
+    fn register_accel(
+        &self,
+        accel_name: &str,
+        bin_file: &str,
+        dtbo_file: &str,
+        json_file: Option<&str>,
+        overwrite: bool,
+    ) -> PlatformResult<()> {
+        fpgautil::register_accel(accel_name, bin_file, dtbo_file, json_file, overwrite)
+    }
+
+    fn unregister_accel(&self, accel_name: &str) -> PlatformResult<()> {
+        fpgautil::unregister_accel(accel_name)
+    }
+
+    // uploads have always gone through the uidmng helpers, independent of allow_sudo
+    fn write_file(&self, path: &str, data: &[u8]) -> PlatformResult<()> {
+        uidmng::write_sudo(path, data)
+    }
+
+    fn append_file(&self, path: &str, data: &[u8]) -> PlatformResult<()> {
+        uidmng::append_sudo(path, data)
+    }
+
+    fn remove_file(&self, path: &Path) -> PlatformResult<()> {
+        let args = ["rm".as_ref(), "-f".as_ref(), "--".as_ref(), path.as_os_str()];
+        self.or_sudo(std::fs::remove_file(path), &args)
+    }
+
+    fn rename_file(&self, from: &Path, to: &Path) -> PlatformResult<()> {
+        let args = ["mv".as_ref(), "-f".as_ref(), "--".as_ref(), from.as_os_str(), to.as_os_str()];
+        self.or_sudo(std::fs::rename(from, to), &args)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> PlatformResult<()> {
+        let args = ["cp".as_ref(), "-f".as_ref(), "--".as_ref(), from.as_os_str(), to.as_os_str()];
+        self.or_sudo(std::fs::copy(from, to).map(|_| ()), &args)
+    }
+
+    fn create_dir(&self, path: &Path) -> PlatformResult<()> {
+        let args = ["mkdir".as_ref(), "-p".as_ref(), "--".as_ref(), path.as_os_str()];
+        self.or_sudo(std::fs::create_dir_all(path), &args)
+    }
+
+    fn dts_to_dtb(&self, dts: &str) -> PlatformResult<Vec<u8>> {
+        fpgautil::dtc_with_str(dts)
+    }
+
+    fn bitstream_to_bin(&self, bit_file: &str, bin_file: &str, arch: &str) -> PlatformResult<()> {
+        fpgautil::xlnx_bitstream_to_bin(bit_file, bin_file, arch)
+    }
+
+    fn load_remoteproc(&self, id: usize, name: &str) -> PlatformResult<()> {
+        fpgautil::load_remoteproc_from_firmware(id, name)
+    }
+
+    fn start_remoteproc(&self, id: usize) -> PlatformResult<()> {
+        fpgautil::start_remoteproc(id)
+    }
+
+    fn stop_remoteproc(&self, id: usize) -> PlatformResult<()> {
+        fpgautil::stop_remoteproc(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn overlay(name: &str, status: &str, path: &str) -> Overlay {
+        Overlay {
+            name: name.to_string(),
+            status: status.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn reconcile_keeps_applied_accelerators() {
+        let slots = Slots::from([
+            (0, "k26".to_string()),
+            (1, "gone".to_string()),
+            (2, "unapplied".to_string()),
+            (3, "renamed".to_string()),
+        ]);
+        let overlays = [
+            overlay("k26", "applied", ""),
+            overlay("unapplied", "unapplied", ""),
+            overlay("overlay0", "applied", "xilinx/renamed/renamed.dtbo"),
+        ];
+        let slots = reconcile(slots, &overlays);
+        assert_eq!(
+            slots.into_iter().collect::<Vec<_>>(),
+            [(0, "k26".to_string()), (3, "renamed".to_string())]
+        );
+    }
+
+    #[test]
+    fn slots_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("run/slots.json");
+        assert!(read_slots(&path).is_empty());
+        let slots = Slots::from([(0, "k26".to_string()), (5, "other".to_string())]);
+        write_slots(&path, &slots).unwrap();
+        assert_eq!(read_slots(&path), slots);
+        std::fs::write(&path, "not json").unwrap();
+        assert!(read_slots(&path).is_empty());
+    }
+}
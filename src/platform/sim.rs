@@ -0,0 +1,392 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use jelly_fpgautil as fpgautil;
+use tracing::info;
+
+use super::{
+    ACCEL_DIR, DEFAULT_FPGA_MANAGER, FIRMWARE_CLASS_PATH, FPGA_MANAGER_CLASS, KERNEL_FIRMWARE_DIR,
+    OVERLAY_DIR, PlatformBackend, PlatformResult, REMOTEPROC_CLASS,
+};
+use crate::firmware::{self, path};
+use crate::jelly_fpga_control::FirmwareType;
+use crate::logging;
+
+const SIM_REMOTEPROCS: usize = 2;
+const SHELL_JSON: &str = "shell.json";
+// flat shell, used when an accelerator is registered without a shell.json
+const DEFAULT_SHELL_JSON: &str = r#"{
+    "shell_type" : "XRT_FLAT",
+    "num_slots": "1"
+}
+"#;
+
+fn error(kind: io::ErrorKind, message: String) -> Box<dyn Error> {
+    io::Error::new(kind, message).into()
+}
+
+fn read_attr(path: &Path) -> String {
+    std::fs::read_to_string(path)
+        .map(|value| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn write_attr(path: &Path, value: &str) -> io::Result<()> {
+    std::fs::write(path, format!("{}\n", value))
+}
+
+fn file_name(path: &str) -> PlatformResult<&str> {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| {
+            error(
+                io::ErrorKind::InvalidInput,
+                format!("{}: no file name", path),
+            )
+        })
+}
+
+/// A board without hardware. The FPGA manager, configfs overlays and
+/// remoteprocs are modelled as sysfs-like files below a directory, so the
+/// rest of the server reads them exactly like on a real board.
+#[derive(Debug)]
+pub struct SimBackend {
+    root: PathBuf,
+    // slot -> accelerator
+    slots: Mutex<BTreeMap<i32, String>>,
+}
+
+impl SimBackend {
+    /// Sets up the simulated system below `root`. Firmware already stored
+    /// there is kept, overlays, slots and device states start over.
+    pub fn new(root: &Path) -> io::Result<Self> {
+        let sim = SimBackend {
+            root: root.to_path_buf(),
+            slots: Mutex::new(BTreeMap::new()),
+        };
+        std::fs::create_dir_all(sim.system_path(KERNEL_FIRMWARE_DIR).join(ACCEL_DIR))?;
+
+        let overlays = sim.system_path(OVERLAY_DIR);
+        if overlays.exists() {
+            std::fs::remove_dir_all(&overlays)?;
+        }
+        std::fs::create_dir_all(&overlays)?;
+
+        let manager = sim.system_path(FPGA_MANAGER_CLASS).join(DEFAULT_FPGA_MANAGER);
+        std::fs::create_dir_all(&manager)?;
+        write_attr(&manager.join("name"), "Simulated FPGA Manager")?;
+        write_attr(&manager.join("state"), "operating")?;
+        write_attr(&manager.join("flags"), "0")?;
+        write_attr(&manager.join("status"), "")?;
+
+        for id in 0..SIM_REMOTEPROCS {
+            let remoteproc = sim.remoteproc_path(id);
+            std::fs::create_dir_all(&remoteproc)?;
+            write_attr(&remoteproc.join("name"), &format!("sim-rproc{}", id))?;
+            write_attr(&remoteproc.join("state"), "offline")?;
+            write_attr(&remoteproc.join("firmware"), "")?;
+        }
+
+        let class_path = sim.system_path(FIRMWARE_CLASS_PATH);
+        if let Some(parent) = class_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_attr(&class_path, "")?;
+
+        info!(target: logging::SERVER, root = %root.display(), "simulated platform");
+        Ok(sim)
+    }
+
+    fn manager_path(&self) -> PathBuf {
+        self.system_path(FPGA_MANAGER_CLASS).join(DEFAULT_FPGA_MANAGER)
+    }
+
+    fn remoteproc_path(&self, id: usize) -> PathBuf {
+        self.system_path(REMOTEPROC_CLASS)
+            .join(format!("remoteproc{}", id))
+    }
+
+    fn accel_path(&self, accel_name: &str) -> PlatformResult<PathBuf> {
+        path::check_component(accel_name)?;
+        Ok(self
+            .system_path(KERNEL_FIRMWARE_DIR)
+            .join(ACCEL_DIR)
+            .join(accel_name))
+    }
+
+    // like the kernel, firmware_class.path is tried before /lib/firmware
+    fn find_firmware(&self, name: &str) -> PlatformResult<PathBuf> {
+        path::check_name(name)?;
+        let class_path = read_attr(&self.system_path(FIRMWARE_CLASS_PATH));
+        let mut dirs = Vec::new();
+        if !class_path.is_empty() {
+            dirs.push(PathBuf::from(class_path));
+        }
+        dirs.push(self.system_path(KERNEL_FIRMWARE_DIR));
+        dirs.iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .ok_or_else(|| {
+                error(
+                    io::ErrorKind::NotFound,
+                    format!("firmware {} not found", name),
+                )
+            })
+    }
+
+    fn program(&self, name: &str) -> PlatformResult<()> {
+        let path = self.find_firmware(name)?;
+        let manager = self.manager_path();
+        let state = manager.join("state");
+        let status = manager.join("status");
+        match firmware::detect_file(&path)? {
+            FirmwareType::Bit | FirmwareType::Bin => {
+                write_attr(&state, "operating")?;
+                write_attr(&status, "")?;
+                info!(target: logging::FPGA, name, "simulated bitstream loaded");
+                Ok(())
+            }
+            _ => {
+                write_attr(&state, "write error")?;
+                write_attr(&status, "reconfig incompatible image")?;
+                Err(error(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: not a bitstream", name),
+                ))
+            }
+        }
+    }
+
+    fn apply_overlay(&self, overlay: &str, name: &str) -> PlatformResult<()> {
+        let path = self.find_firmware(name)?;
+        if firmware::detect_file(&path)? != FirmwareType::Dtbo {
+            return Err(error(
+                io::ErrorKind::InvalidData,
+                format!("{}: not a device tree overlay", name),
+            ));
+        }
+        let dir = self.system_path(OVERLAY_DIR).join(overlay);
+        if dir.exists() {
+            return Err(error(
+                io::ErrorKind::AlreadyExists,
+                format!("overlay {} is already applied", overlay),
+            ));
+        }
+        std::fs::create_dir(&dir)?;
+        write_attr(&dir.join("path"), name)?;
+        write_attr(&dir.join("status"), "applied")?;
+        info!(target: logging::FPGA, overlay, name, "simulated overlay applied");
+        Ok(())
+    }
+
+    fn running(&self, id: usize) -> PlatformResult<(PathBuf, bool)> {
+        let dir = self.remoteproc_path(id);
+        if !dir.is_dir() {
+            return Err(error(
+                io::ErrorKind::NotFound,
+                format!("remoteproc{} not found", id),
+            ));
+        }
+        let running = read_attr(&dir.join("state")) == "running";
+        Ok((dir, running))
+    }
+}
+
+impl PlatformBackend for SimBackend {
+    fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn load(&self, accel_name: &str) -> PlatformResult<i32> {
+        let dir = self.accel_path(accel_name)?;
+        if !dir.is_dir() {
+            return Err(error(
+                io::ErrorKind::NotFound,
+                format!("accelerator {} is not registered", accel_name),
+            ));
+        }
+        let mut bin = None;
+        let mut dtbo = None;
+        for entry in std::fs::read_dir(&dir)?.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".bin") || name.ends_with(".bit") {
+                bin = Some(name);
+            } else if name.ends_with(".dtbo") {
+                dtbo = Some(name);
+            }
+        }
+        let (Some(bin), Some(dtbo)) = (bin, dtbo) else {
+            return Err(error(
+                io::ErrorKind::NotFound,
+                format!("accelerator {} has no bitstream or overlay", accel_name),
+            ));
+        };
+
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = (0..)
+            .find(|slot| !slots.contains_key(slot))
+            .unwrap_or_default();
+        self.program(&format!("{}/{}/{}", ACCEL_DIR, accel_name, bin))?;
+        self.apply_overlay(
+            accel_name,
+            &format!("{}/{}/{}", ACCEL_DIR, accel_name, dtbo),
+        )?;
+        slots.insert(slot, accel_name.to_string());
+        Ok(slot)
+    }
+
+    fn unload(&self, slot: i32) -> PlatformResult<()> {
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(accel_name) = slots.remove(&slot) else {
+            return Err(error(
+                io::ErrorKind::NotFound,
+                format!("slot {} is not loaded", slot),
+            ));
+        };
+        let overlay = self.system_path(OVERLAY_DIR).join(&accel_name);
+        if overlay.exists() {
+            std::fs::remove_dir_all(overlay)?;
+        }
+        info!(target: logging::FPGA, slot, accel_name, "simulated slot unloaded");
+        Ok(())
+    }
+
+    fn slots(&self) -> Vec<(i32, String)> {
+        let slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        slots.iter().map(|(slot, accel)| (*slot, accel.clone())).collect()
+    }
+
+    fn load_bitstream(&self, name: &str) -> PlatformResult<()> {
+        self.program(name)
+    }
+
+    fn load_dtbo(&self, name: &str) -> PlatformResult<()> {
+        let overlay = Path::new(name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.apply_overlay(&overlay, name)
+    }
+
+    fn register_accel(
+        &self,
+        accel_name: &str,
+        bin_file: &str,
+        dtbo_file: &str,
+        json_file: Option<&str>,
+        overwrite: bool,
+    ) -> PlatformResult<()> {
+        let dir = self.accel_path(accel_name)?;
+        if dir.exists() {
+            if !overwrite {
+                return Err(error(
+                    io::ErrorKind::AlreadyExists,
+                    format!("accelerator {} is already registered", accel_name),
+                ));
+            }
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        std::fs::copy(bin_file, dir.join(file_name(bin_file)?))?;
+        std::fs::copy(dtbo_file, dir.join(file_name(dtbo_file)?))?;
+        match json_file {
+            Some(json_file) => std::fs::copy(json_file, dir.join(SHELL_JSON)).map(|_| ())?,
+            None => std::fs::write(dir.join(SHELL_JSON), DEFAULT_SHELL_JSON)?,
+        }
+        Ok(())
+    }
+
+    fn unregister_accel(&self, accel_name: &str) -> PlatformResult<()> {
+        let dir = self.accel_path(accel_name)?;
+        if !dir.is_dir() {
+            return Err(error(
+                io::ErrorKind::NotFound,
+                format!("accelerator {} is not registered", accel_name),
+            ));
+        }
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> PlatformResult<()> {
+        Ok(std::fs::write(path, data)?)
+    }
+
+    fn append_file(&self, path: &str, data: &[u8]) -> PlatformResult<()> {
+        OpenOptions::new()
+            .append(true)
+            .open(path)?
+            .write_all(data)?;
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> PlatformResult<()> {
+        Ok(std::fs::remove_file(path)?)
+    }
+
+    fn rename_file(&self, from: &Path, to: &Path) -> PlatformResult<()> {
+        Ok(std::fs::rename(from, to)?)
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> PlatformResult<()> {
+        std::fs::copy(from, to)?;
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> PlatformResult<()> {
+        Ok(std::fs::create_dir_all(path)?)
+    }
+
+    // host tools, they work the same without a board
+    fn dts_to_dtb(&self, dts: &str) -> PlatformResult<Vec<u8>> {
+        fpgautil::dtc_with_str(dts)
+    }
+
+    fn bitstream_to_bin(&self, bit_file: &str, bin_file: &str, arch: &str) -> PlatformResult<()> {
+        fpgautil::xlnx_bitstream_to_bin(bit_file, bin_file, arch)
+    }
+
+    fn load_remoteproc(&self, id: usize, name: &str) -> PlatformResult<()> {
+        let (dir, running) = self.running(id)?;
+        if running {
+            return Err(format!("remoteproc{} is running", id).into());
+        }
+        let path = self.find_firmware(name)?;
+        if firmware::detect_file(&path)? != FirmwareType::Elf {
+            return Err(error(
+                io::ErrorKind::InvalidData,
+                format!("{}: not an ELF file", name),
+            ));
+        }
+        write_attr(&dir.join("firmware"), name)?;
+        Ok(())
+    }
+
+    fn start_remoteproc(&self, id: usize) -> PlatformResult<()> {
+        let (dir, running) = self.running(id)?;
+        if running {
+            return Err(format!("remoteproc{} is already running", id).into());
+        }
+        if read_attr(&dir.join("firmware")).is_empty() {
+            return Err(format!("remoteproc{} has no firmware", id).into());
+        }
+        write_attr(&dir.join("state"), "running")?;
+        info!(target: logging::REMOTEPROC, id, "simulated remoteproc started");
+        Ok(())
+    }
+
+    fn stop_remoteproc(&self, id: usize) -> PlatformResult<()> {
+        let (dir, running) = self.running(id)?;
+        if !running {
+            return Err(format!("remoteproc{} is not running", id).into());
+        }
+        write_attr(&dir.join("state"), "offline")?;
+        info!(target: logging::REMOTEPROC, id, "simulated remoteproc stopped");
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+pub type SessionId = u64;
+
+// handles opened without a session header belong to the anonymous session
+pub const ANONYMOUS_SESSION: SessionId = 0;
+pub const SESSION_HEADER: &str = "x-jelly-session";
+
+#[derive(Debug)]
+struct Session {
+    timeout: Duration,
+    last_seen: Instant,
+    watched: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct SessionManager {
+    sessions: HashMap<SessionId, Session>,
+}
+
+// session ids act as capabilities, so they are not allocated sequentially
+fn random_id() -> SessionId {
+    let mut hasher = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u128(now.as_nanos());
+    hasher.finish()
+}
+
+impl SessionManager {
+    pub fn new() -> Self {
+        SessionManager {
+            sessions: HashMap::new(),
+        }
+    }
+
+    pub fn open(&mut self, timeout: Duration) -> SessionId {
+        let mut id = random_id();
+        while id == ANONYMOUS_SESSION || self.sessions.contains_key(&id) {
+            id = random_id();
+        }
+        self.sessions.insert(
+            id,
+            Session {
+                timeout,
+                last_seen: Instant::now(),
+                watched: false,
+            },
+        );
+        id
+    }
+
+    pub fn contains(&self, id: SessionId) -> bool {
+        self.sessions.contains_key(&id)
+    }
+
+    pub fn touch(&mut self, id: SessionId) -> bool {
+        match self.sessions.get_mut(&id) {
+            Some(session) => {
+                session.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn set_watched(&mut self, id: SessionId, watched: bool) -> bool {
+        match self.sessions.get_mut(&id) {
+            Some(session) => {
+                session.watched = watched;
+                session.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn close(&mut self, id: SessionId) -> bool {
+        self.sessions.remove(&id).is_some()
+    }
+
+    pub fn expire(&mut self, now: Instant) -> Vec<SessionId> {
+        let expired: Vec<SessionId> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| !s.watched && now.duration_since(s.last_seen) > s.timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            self.sessions.remove(id);
+        }
+        expired
+    }
+}
+
+pub fn session_id(metadata: &MetadataMap) -> Result<SessionId, Status> {
+    match metadata.get(SESSION_HEADER) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| Status::invalid_argument(format!("invalid {} header", SESSION_HEADER))),
+        None => Ok(ANONYMOUS_SESSION),
+    }
+}
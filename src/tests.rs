@@ -0,0 +1,909 @@
+// End-to-end tests: the service runs in-process on an ephemeral port with the
+// simulated platform, and is driven through the generated client.
+
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use tonic::Code;
+use tonic::transport::Channel;
+use tonic::transport::server::TcpIncoming;
+
+use super::*;
+use jelly_fpga_control::jelly_fpga_control_client::JellyFpgaControlClient;
+use platform::sim::SimBackend;
+
+const MMAP_SIZE: usize = 0x1000;
+
+struct TestServer {
+    client: JellyFpgaControlClient<Channel>,
+    dir: TempDir,
+}
+
+impl TestServer {
+    async fn start() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    async fn with_config(config: Config) -> Self {
+        Self::with_tokens(config, None).await
+    }
+
+    async fn with_tokens(mut config: Config, tokens: Option<auth::Tokens>) -> Self {
+        let dir = TempDir::new().unwrap();
+        let firmware_dir = dir.path().join("firmware");
+        std::fs::create_dir(&firmware_dir).unwrap();
+        config.firmware.dir = firmware_dir.to_string_lossy().into_owned();
+
+        let platform: Arc<dyn PlatformBackend> =
+            Arc::new(SimBackend::new(&dir.path().join("sim")).unwrap());
+        let root = FirmwareRoot::new(&config.firmware.dir);
+        let stager = Stager::new(&root, &config.firmware, platform.as_ref()).unwrap();
+        let audit = AuditLog::open(&config.audit).unwrap();
+        let service = JellyFpgaControlService::new(&config, platform, stager, audit);
+
+        let incoming = TcpIncoming::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = incoming.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .layer(RequestLogLayer)
+                .add_service(JellyFpgaControlServer::with_interceptor(
+                    service,
+                    AuthInterceptor::new(tokens),
+                ))
+                .serve_with_incoming(incoming),
+        );
+        let client = JellyFpgaControlClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+        TestServer { client, dir }
+    }
+
+    fn firmware_dir(&self) -> PathBuf {
+        self.dir.path().join("firmware")
+    }
+
+    // a zero filled file to map with OpenMmap
+    fn mmap_file(&self, name: &str) -> String {
+        let path = self.dir.path().join(name);
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(MMAP_SIZE as u64).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    async fn open_mmap(&mut self, path: &str, unit: u64) -> u32 {
+        let request = OpenMmapRequest {
+            path: path.to_string(),
+            offset: 0,
+            size: MMAP_SIZE as u64,
+            unit,
+        };
+        self.client.open_mmap(request).await.unwrap().into_inner().id
+    }
+
+    async fn upload(&mut self, request: UploadFirmwareRequest) -> Result<(), Status> {
+        let stream = tokio_stream::iter(vec![request]);
+        self.client.upload_firmware(stream).await.map(|_| ())
+    }
+}
+
+fn upload_request(name: &str, data: &[u8]) -> UploadFirmwareRequest {
+    UploadFirmwareRequest {
+        name: name.to_string(),
+        data: data.to_vec(),
+        ..Default::default()
+    }
+}
+
+fn in_session<T>(session: u64, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert(session::SESSION_HEADER, session.to_string().parse().unwrap());
+    request
+}
+
+fn with_token<T>(token: &str, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    let value = format!("Bearer {}", token).parse().unwrap();
+    request.metadata_mut().insert(auth::AUTHORIZATION_HEADER, value);
+    request
+}
+
+fn read_mem(id: u32, offset: u64, size: u64) -> ReadMemRequest {
+    ReadMemRequest { id, offset, size }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    firmware::sha256_hex(Sha256::new().chain_update(data))
+}
+
+// a raw bitstream: padding followed by the sync word
+fn bitstream() -> Vec<u8> {
+    [vec![0xff; 32], vec![0xaa, 0x99, 0x55, 0x66], vec![0; 28]].concat()
+}
+
+fn overlay() -> Vec<u8> {
+    [vec![0xd0, 0x0d, 0xfe, 0xed], vec![0; 60]].concat()
+}
+
+fn elf() -> Vec<u8> {
+    [vec![0x7f, b'E', b'L', b'F'], vec![0; 60]].concat()
+}
+
+fn batch_op(id: u32, op_type: BatchOpType, address: u64, data: u64, mask: u64) -> BatchOp {
+    BatchOp {
+        id,
+        r#type: op_type as i32,
+        reg: true,
+        address,
+        size: 4,
+        data,
+        mask,
+    }
+}
+
+#[tokio::test]
+async fn get_version() {
+    let mut server = TestServer::start().await;
+    let version = server.client.get_version(Empty {}).await.unwrap().into_inner();
+    assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+}
+
+#[tokio::test]
+async fn mmap_integer_access() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let writes = [(0, 1, 0x12), (2, 2, 0x3456), (4, 4, 0x789a_bcde), (8, 8, u64::MAX)];
+    for (offset, size, data) in writes {
+        let request = WriteMemURequest { id, offset, data, size };
+        client.write_mem_u(request).await.unwrap();
+        let read = client.read_mem_u(read_mem(id, offset, size)).await.unwrap().into_inner();
+        assert_eq!(read.data, data);
+    }
+    let read = client.read_mem_i(read_mem(id, 8, 4)).await.unwrap().into_inner();
+    assert_eq!(read.data, -1);
+
+    // registers are addressed in units of 4 bytes
+    let request = WriteRegIRequest { id, reg: 5, data: -2, size: 4 };
+    client.write_reg_i(request).await.unwrap();
+    let read = client.read_mem_i(read_mem(id, 20, 4)).await.unwrap().into_inner();
+    assert_eq!(read.data, -2);
+    let request = ReadRegRequest { id, reg: 5, size: 4 };
+    let read = client.read_reg_u(request).await.unwrap().into_inner();
+    assert_eq!(read.data, 0xffff_fffe);
+
+    // the mapping is shared with the file
+    let contents = std::fs::read(&path).unwrap();
+    assert_eq!(&contents[4..8], &0x789a_bcdeu32.to_ne_bytes());
+}
+
+#[tokio::test]
+async fn mmap_float_access() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 8).await;
+    let client = &mut server.client;
+
+    let request = WriteMemF32Request { id, offset: 4, data: 1.25 };
+    client.write_mem_f32(request).await.unwrap();
+    let read = client.read_mem_f32(read_mem(id, 4, 0)).await.unwrap().into_inner();
+    assert_eq!(read.data, 1.25);
+
+    let request = WriteRegF64Request { id, reg: 2, data: -0.5 };
+    client.write_reg_f64(request).await.unwrap();
+    let read = client.read_mem_f64(read_mem(id, 16, 0)).await.unwrap().into_inner();
+    assert_eq!(read.data, -0.5);
+    let read = client.read_reg_f64(ReadRegRequest { id, reg: 2, size: 0 }).await.unwrap();
+    assert_eq!(read.into_inner().data, -0.5);
+}
+
+#[tokio::test]
+async fn mmap_access_errors() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let missing = server.dir.path().join("missing").to_string_lossy().into_owned();
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let request = OpenMmapRequest { path: missing, offset: 0, size: 0x100, unit: 0 };
+    let err = client.open_mmap(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    let err = client.read_mem_u(read_mem(id, MMAP_SIZE as u64, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let err = client.read_mem_u(read_mem(id, MMAP_SIZE as u64 - 2, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let err = client.read_mem_u(read_mem(id, 2, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client.read_mem_u(read_mem(id, 0, 3)).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client.read_mem_u(read_mem(id + 100, 0, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    // offsets past 4 GiB must not wrap into range where usize is 32 bits
+    let huge = u64::from(u32::MAX) + 1;
+    let err = client.read_mem_u(read_mem(id, huge, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = WriteMemURequest { id, offset: huge + 4, data: 1, size: 4 };
+    let err = client.write_mem_u(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = MemCopyFromRequest { id, offset: huge, size: 4 };
+    let err = client.mem_copy_from(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = MemCopyFromRequest { id, offset: 0, size: huge + 4 };
+    let err = client.mem_copy_from(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = ReadRegRequest { id, reg: huge / 4, size: 4 };
+    let err = client.read_reg_u(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let batch = ExecuteBatchRequest {
+        ops: vec![batch_op(id, BatchOpType::Read, huge, 0, 0)],
+        stop_on_error: true,
+    };
+    let results = client.execute_batch(batch).await.unwrap().into_inner().results;
+    assert_eq!(results[0].code, Code::OutOfRange as i32);
+
+    let request = ReadRegRequest { id, reg: u64::MAX, size: 4 };
+    let err = client.read_reg_u(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+}
+
+#[tokio::test]
+async fn mem_copy() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 0).await;
+    let client = &mut server.client;
+
+    let data: Vec<u8> = (0..=255).collect();
+    let request = MemCopyToRequest { id, offset: 0x101, data: data.clone() };
+    client.mem_copy_to(request).await.unwrap();
+    let request = MemCopyFromRequest { id, offset: 0x101, size: data.len() as u64 };
+    let read = client.mem_copy_from(request).await.unwrap().into_inner();
+    assert_eq!(read.data, data);
+
+    let request = MemCopyToRequest { id, offset: MMAP_SIZE as u64 - 1, data: vec![0; 2] };
+    let err = client.mem_copy_to(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = MemCopyFromRequest { id, offset: 0, size: MMAP_SIZE as u64 + 1 };
+    let err = client.mem_copy_from(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+}
+
+#[tokio::test]
+async fn subclone() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let request = SubcloneRequest { id, offset: 0x100, size: 0x40, unit: 0 };
+    let sub = client.subclone(request).await.unwrap().into_inner().id;
+    let size = client.get_size(GetSizeRequest { id: sub }).await.unwrap();
+    assert_eq!(size.into_inner().size, 0x40);
+    let parent = client.get_addr(GetAddrRequest { id }).await.unwrap().into_inner().addr;
+    let child = client.get_addr(GetAddrRequest { id: sub }).await.unwrap().into_inner().addr;
+    assert_eq!(child, parent + 0x100);
+
+    // the unit is inherited and writes show through the parent
+    let request = WriteRegURequest { id: sub, reg: 1, data: 0xcafe, size: 4 };
+    client.write_reg_u(request).await.unwrap();
+    let read = client.read_mem_u(read_mem(id, 0x104, 4)).await.unwrap().into_inner();
+    assert_eq!(read.data, 0xcafe);
+
+    let err = client.read_mem_u(read_mem(sub, 0x40, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = SubcloneRequest { id, offset: 0xff0, size: 0x20, unit: 0 };
+    let err = client.subclone(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+    let request = SubcloneRequest { id: sub + 100, offset: 0, size: 0, unit: 0 };
+    let err = client.subclone(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn close_accessor() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    client.close(CloseRequest { id }).await.unwrap();
+    let err = client.read_mem_u(read_mem(id, 0, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let err = client.close(CloseRequest { id }).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn sim_accessor() {
+    let mut server = TestServer::start().await;
+    let client = &mut server.client;
+
+    let request = OpenSimRequest {
+        name: "regs".into(),
+        size: 0x100,
+        phys_addr: 0xa000_0000,
+        unit: 4,
+    };
+    let id = client.open_sim(request).await.unwrap().into_inner().id;
+    let phys = client.get_phys_addr(GetPhysAddrRequest { id }).await.unwrap();
+    assert_eq!(phys.into_inner().phys_addr, 0xa000_0000);
+    let request = SubcloneRequest { id, offset: 0x80, size: 0, unit: 0 };
+    let sub = client.subclone(request).await.unwrap().into_inner().id;
+    let phys = client.get_phys_addr(GetPhysAddrRequest { id: sub }).await.unwrap();
+    assert_eq!(phys.into_inner().phys_addr, 0xa000_0080);
+    client.read_mem_u(read_mem(sub, 0x7c, 4)).await.unwrap();
+    let err = client.read_mem_u(read_mem(sub, 0x80, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+
+    // opening the name again maps the same memory
+    let request = WriteRegURequest { id: sub, reg: 2, data: 0x1234_5678, size: 4 };
+    client.write_reg_u(request).await.unwrap();
+    let request = OpenSimRequest { name: "regs".into(), size: 0, phys_addr: 0, unit: 4 };
+    let other = client.open_sim(request).await.unwrap().into_inner().id;
+    let read = client.read_mem_u(read_mem(other, 0x88, 4)).await.unwrap().into_inner();
+    assert_eq!(read.data, 0x1234_5678);
+
+    let request = OpenSimRequest { name: "regs".into(), size: 0x200, phys_addr: 0, unit: 4 };
+    let err = client.open_sim(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let request = OpenSimRequest { name: "empty".into(), size: 0, phys_addr: 0, unit: 4 };
+    let err = client.open_sim(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let err = client.read_mem_u(read_mem(id, 2, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    // another session opening the name gets its own memory
+    let request = OpenSessionRequest { timeout_ms: 0 };
+    let session = client.open_session(request).await.unwrap().into_inner().session_id;
+    let request = OpenSimRequest { name: "regs".into(), size: 0x100, phys_addr: 0, unit: 4 };
+    let private = client.open_sim(in_session(session, request)).await.unwrap().into_inner().id;
+    let request = in_session(session, read_mem(private, 0x88, 4));
+    let read = client.read_mem_u(request).await.unwrap().into_inner();
+    assert_eq!(read.data, 0);
+    let request = in_session(session, GetPhysAddrRequest { id: private });
+    let phys = client.get_phys_addr(request).await.unwrap();
+    assert_eq!(phys.into_inner().phys_addr, 0);
+}
+
+#[tokio::test]
+async fn reset_closes_own_accessors() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let anonymous = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let request = OpenSessionRequest { timeout_ms: 0 };
+    let session = client.open_session(request).await.unwrap().into_inner().session_id;
+    let request = OpenMmapRequest { path, offset: 0, size: MMAP_SIZE as u64, unit: 4 };
+    let owned = client.open_mmap(in_session(session, request)).await.unwrap();
+    let owned = owned.into_inner().id;
+
+    client.reset(ResetRequest {}).await.unwrap();
+    let err = client.read_mem_u(read_mem(anonymous, 0, 4)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let request = in_session(session, read_mem(owned, 0, 4));
+    client.read_mem_u(request).await.unwrap();
+
+    client.reset(in_session(session, ResetRequest {})).await.unwrap();
+    let err = client.read_mem_u(in_session(session, read_mem(owned, 0, 4))).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn session_isolation() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let request = OpenSessionRequest { timeout_ms: 0 };
+    let session = client.open_session(request).await.unwrap().into_inner().session_id;
+    let err = client.read_mem_u(in_session(session, read_mem(id, 0, 4))).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    client.share_accessor(ShareAccessorRequest { id, shared: true }).await.unwrap();
+    client.read_mem_u(in_session(session, read_mem(id, 0, 4))).await.unwrap();
+
+    client.close_session(SessionRequest { session_id: session }).await.unwrap();
+    let err = client.read_mem_u(in_session(session, read_mem(id, 0, 4))).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn accessor_feature_and_access_list() {
+    let mut config = Config::default();
+    config.access.mmap = Some(Vec::new());
+    let mut server = TestServer::with_config(config).await;
+    let path = server.mmap_file("mmap.bin");
+    let request = OpenMmapRequest { path, offset: 0, size: 0x100, unit: 0 };
+    let err = server.client.open_mmap(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::PermissionDenied);
+
+    let mut config = Config::default();
+    config.features.accessor = false;
+    let mut server = TestServer::with_config(config).await;
+    let request = OpenSimRequest { name: "regs".into(), size: 0x100, phys_addr: 0, unit: 0 };
+    let err = server.client.open_sim(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn firmware_upload_list_download_remove() {
+    let mut server = TestServer::start().await;
+    let data = bitstream();
+
+    // sent in chunks, the name only in the first message
+    let first = UploadFirmwareRequest {
+        name: "top.bin".into(),
+        data: data[..20].to_vec(),
+        sha256: sha256_hex(&data),
+        has_size: true,
+        size: data.len() as u64,
+    };
+    let rest = UploadFirmwareRequest { data: data[20..].to_vec(), ..Default::default() };
+    let stream = tokio_stream::iter(vec![first, rest]);
+    server.client.upload_firmware(stream).await.unwrap();
+    assert_eq!(std::fs::read(server.firmware_dir().join("top.bin")).unwrap(), data);
+
+    let request = ListFirmwareRequest { pattern: "*.bin".into(), recursive: true };
+    let files = server.client.list_firmware(request).await.unwrap().into_inner().files;
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "top.bin");
+    assert_eq!(files[0].size, data.len() as u64);
+    assert_eq!(files[0].sha256, sha256_hex(&data));
+    assert_eq!(files[0].r#type(), FirmwareType::Bin);
+
+    let request = DownloadFirmwareRequest {
+        name: "top.bin".into(),
+        chunk_size: 16,
+        ..Default::default()
+    };
+    let mut stream = server.client.download_firmware(request).await.unwrap().into_inner();
+    let mut downloaded = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        downloaded.extend_from_slice(&chunk.data);
+        if chunk.last {
+            assert_eq!(chunk.sha256, sha256_hex(&data));
+        }
+    }
+    assert_eq!(downloaded, data);
+
+    let request = RemoveFirmwareRequest { name: "top.bin".into() };
+    server.client.remove_firmware(request.clone()).await.unwrap();
+    assert!(!server.firmware_dir().join("top.bin").exists());
+    let err = server.client.remove_firmware(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn firmware_upload_errors() {
+    let mut server = TestServer::start().await;
+    let data = bitstream();
+
+    let mut request = upload_request("bad.bin", &data);
+    request.sha256 = sha256_hex(b"something else");
+    let err = server.upload(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::DataLoss);
+    let mut request = upload_request("short.bin", &data);
+    request.has_size = true;
+    request.size = data.len() as u64 + 1;
+    let err = server.upload(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::DataLoss);
+    // nothing is left behind by a failed upload
+    assert_eq!(std::fs::read_dir(server.firmware_dir()).unwrap().count(), 0);
+
+    for name in ["../escape.bin", "/tmp/absolute.bin", ""] {
+        let err = server.upload(upload_request(name, &data)).await.unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument, "{:?}", name);
+    }
+    let request = RemoveFirmwareRequest { name: "../firmware".into() };
+    let err = server.client.remove_firmware(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn load_uploaded_bitstream() {
+    let mut server = TestServer::start().await;
+    server.upload(upload_request("top.bin", &bitstream())).await.unwrap();
+    server.upload(upload_request("top.txt", b"not a bitstream")).await.unwrap();
+
+    let request = LoadBitstreamRequest { name: "top.bin".into() };
+    server.client.load_bitstream(request).await.unwrap();
+    let request = LoadBitstreamRequest { name: "top.txt".into() };
+    let err = server.client.load_bitstream(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    let request = LoadBitstreamRequest { name: "missing.bin".into() };
+    let err = server.client.load_bitstream(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn execute_batch() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let ops = vec![
+        batch_op(id, BatchOpType::Write, 1, 0x00ff, 0),
+        batch_op(id, BatchOpType::Modify, 1, 0x1200, 0xff00),
+        batch_op(id, BatchOpType::Read, 1, 0, 0),
+    ];
+    let request = ExecuteBatchRequest { ops, stop_on_error: false };
+    let response = client.execute_batch(request).await.unwrap().into_inner();
+    assert!(response.result);
+    let data: Vec<u64> = response.results.iter().map(|r| r.data).collect();
+    assert_eq!(data, [0, 0x00ff, 0x12ff]);
+
+    let ops = vec![
+        batch_op(id, BatchOpType::Read, 0x1000, 0, 0),
+        batch_op(id, BatchOpType::Read, 1, 0, 0),
+    ];
+    let request = ExecuteBatchRequest { ops: ops.clone(), stop_on_error: false };
+    let response = client.execute_batch(request).await.unwrap().into_inner();
+    assert!(!response.result);
+    assert_eq!(response.results.len(), 2);
+    assert_eq!(response.results[0].code, Code::OutOfRange as i32);
+    assert!(response.results[1].result);
+    let request = ExecuteBatchRequest { ops, stop_on_error: true };
+    let response = client.execute_batch(request).await.unwrap().into_inner();
+    assert_eq!(response.results.len(), 1);
+}
+
+#[tokio::test]
+async fn register_session() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+
+    let commands = vec![
+        RegisterCommand { seq: 1, op: Some(batch_op(id, BatchOpType::Write, 2, 7, 0)) },
+        RegisterCommand { seq: 2, op: Some(batch_op(id, BatchOpType::Read, 2, 0, 0)) },
+        RegisterCommand { seq: 3, op: Some(batch_op(id + 100, BatchOpType::Read, 0, 0, 0)) },
+    ];
+    let stream = tokio_stream::iter(commands);
+    let mut results = server.client.register_session(stream).await.unwrap().into_inner();
+    let mut received = Vec::new();
+    while let Some(result) = results.message().await.unwrap() {
+        received.push(result);
+    }
+    assert_eq!(received.len(), 3);
+    assert_eq!((received[1].seq, received[1].data), (2, 7));
+    assert!(!received[2].result);
+    assert_eq!(received[2].code, Code::NotFound as i32);
+}
+
+#[tokio::test]
+async fn register_session_read_only() {
+    let tokens = auth::parse_tokens("read-only reader-token").unwrap();
+    let mut server = TestServer::with_tokens(Config::default(), Some(tokens)).await;
+    let path = server.mmap_file("mmap.bin");
+    let request = OpenMmapRequest { path, offset: 0, size: MMAP_SIZE as u64, unit: 4 };
+    let response = server.client.open_mmap(with_token("reader-token", request)).await;
+    let id = response.unwrap().into_inner().id;
+
+    let commands = vec![
+        RegisterCommand { seq: 1, op: Some(batch_op(id, BatchOpType::Read, 2, 0, 0)) },
+        RegisterCommand { seq: 2, op: Some(batch_op(id, BatchOpType::Write, 2, 7, 0)) },
+        RegisterCommand { seq: 3, op: Some(batch_op(id, BatchOpType::Read, 2, 0, 0)) },
+    ];
+    let request = with_token("reader-token", tokio_stream::iter(commands));
+    let mut results = server.client.register_session(request).await.unwrap().into_inner();
+    let mut received = Vec::new();
+    while let Some(result) = results.message().await.unwrap() {
+        received.push(result);
+    }
+    assert_eq!(received.len(), 3);
+    assert!(received[0].result);
+    assert!(!received[1].result);
+    assert_eq!(received[1].code, Code::PermissionDenied as i32);
+    assert_eq!((received[2].result, received[2].data), (true, 0));
+}
+
+#[tokio::test]
+async fn wait_reg_and_mem() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 4).await;
+    let client = &mut server.client;
+
+    let request = WriteRegURequest { id, reg: 3, data: 0x11, size: 4 };
+    client.write_reg_u(request).await.unwrap();
+    let request = WaitRegRequest {
+        id,
+        reg: 3,
+        size: 4,
+        mask: 0x1,
+        expected: 0x1,
+        ..Default::default()
+    };
+    let response = client.wait_reg(request).await.unwrap().into_inner();
+    assert!(response.result);
+    assert_eq!(response.data, 0x11);
+
+    let request = WaitMemRequest {
+        id,
+        offset: 12,
+        size: 4,
+        mask: u64::MAX,
+        expected: 0x11,
+        condition: WaitCondition::Ne as i32,
+        interval_us: 1000,
+        timeout_ms: 5,
+    };
+    let response = client.wait_mem(request).await.unwrap().into_inner();
+    assert!(!response.result);
+    assert!(response.timed_out);
+}
+
+#[tokio::test]
+async fn mem_streams() {
+    let mut server = TestServer::start().await;
+    let path = server.mmap_file("mmap.bin");
+    let id = server.open_mmap(&path, 0).await;
+    let client = &mut server.client;
+
+    let data: Vec<u8> = (0..0x300).map(|i| i as u8).collect();
+    let crc32 = crc32fast::hash(&data);
+    let chunks: Vec<MemWriteStreamRequest> = data
+        .chunks(0x100)
+        .enumerate()
+        .map(|(i, chunk)| MemWriteStreamRequest {
+            id,
+            offset: 0x100,
+            data: chunk.to_vec(),
+            has_crc32: i == 2,
+            crc32: if i == 2 { crc32 } else { 0 },
+        })
+        .collect();
+    let response = client.mem_write_stream(tokio_stream::iter(chunks)).await.unwrap();
+    assert_eq!(response.into_inner().size, data.len() as u64);
+
+    let request = MemReadStreamRequest {
+        id,
+        offset: 0x100,
+        size: data.len() as u64,
+        chunk_size: 0x80,
+        crc32: true,
+    };
+    let mut stream = client.mem_read_stream(request).await.unwrap().into_inner();
+    let mut read = Vec::new();
+    while let Some(chunk) = stream.message().await.unwrap() {
+        read.extend_from_slice(&chunk.data);
+        if chunk.last {
+            assert_eq!(chunk.crc32, crc32);
+        }
+    }
+    assert_eq!(read, data);
+
+    let request = MemWriteStreamRequest {
+        id,
+        offset: MMAP_SIZE as u64 - 0x10,
+        data: vec![0; 0x20],
+        ..Default::default()
+    };
+    let err = client.mem_write_stream(tokio_stream::iter(vec![request])).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+
+    // a read past the end fails before any chunk is sent
+    let request = MemReadStreamRequest {
+        id,
+        offset: MMAP_SIZE as u64 - 0x10,
+        size: 0x20,
+        chunk_size: 0x10,
+        crc32: false,
+    };
+    let err = client.mem_read_stream(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::OutOfRange);
+}
+
+#[tokio::test]
+async fn accel_lifecycle() {
+    let mut server = TestServer::start().await;
+    server.upload(upload_request("top.bin", &bitstream())).await.unwrap();
+    server.upload(upload_request("top.dtbo", &overlay())).await.unwrap();
+    let client = &mut server.client;
+
+    let request = RegisterAccelRequest {
+        accel_name: "top".into(),
+        bin_file: "top.bin".into(),
+        dtbo_file: "top.dtbo".into(),
+        json_file: String::new(),
+        overwrite: false,
+    };
+    client.register_accel(request.clone()).await.unwrap();
+    let err = client.register_accel(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::AlreadyExists);
+
+    let slot = client.load(LoadRequest { name: "top".into() }).await.unwrap().into_inner().slot;
+    client.unload(UnloadRequest { slot }).await.unwrap();
+    client.unregister_accel(UnregisterAccelRequest { accel_name: "top".into() }).await.unwrap();
+    let err = client.load(LoadRequest { name: "top".into() }).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let err = client.load(LoadRequest { name: "../top".into() }).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn remoteproc_lifecycle() {
+    let mut server = TestServer::start().await;
+    server.upload(upload_request("r5.elf", &elf())).await.unwrap();
+    let client = &mut server.client;
+
+    let request = LoadRemoteprocRequest { remoteproc_id: 0, elf_name: "r5.elf".into() };
+    client.load_remoteproc(request).await.unwrap();
+    client.start_remoteproc(RemoteprocIdRequest { remoteproc_id: 0 }).await.unwrap();
+    client.stop_remoteproc(RemoteprocIdRequest { remoteproc_id: 0 }).await.unwrap();
+
+    let request = LoadRemoteprocRequest { remoteproc_id: 9, elf_name: "r5.elf".into() };
+    let err = client.load_remoteproc(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let request = LoadRemoteprocRequest { remoteproc_id: 1, elf_name: "../r5.elf".into() };
+    let err = client.load_remoteproc(request).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn fpga_status() {
+    let mut server = TestServer::start().await;
+    server.upload(upload_request("top.bin", &bitstream())).await.unwrap();
+    server.upload(upload_request("top.txt", b"not a bitstream")).await.unwrap();
+    let client = &mut server.client;
+
+    let request = LoadBitstreamRequest { name: "top.bin".into() };
+    client.load_bitstream(request).await.unwrap();
+    let managers = client.get_fpga_status(Empty {}).await.unwrap().into_inner().managers;
+    assert_eq!(managers.len(), 1);
+    assert_eq!(managers[0].name, "fpga0");
+    assert_eq!(managers[0].state, "operating");
+    assert_eq!(managers[0].flags, "0");
+    assert!(managers[0].status.is_empty());
+    assert_eq!(managers[0].firmware, "top.bin");
+    assert_ne!(managers[0].loaded_ms, 0);
+
+    // a failed load leaves the last successful one in place
+    let request = LoadBitstreamRequest { name: "top.txt".into() };
+    client.load_bitstream(request).await.unwrap_err();
+    let managers = client.get_fpga_status(Empty {}).await.unwrap().into_inner().managers;
+    assert_eq!(managers[0].state, "write error");
+    assert_eq!(managers[0].status, ["reconfig incompatible image"]);
+    assert_eq!(managers[0].firmware, "top.bin");
+}
+
+#[tokio::test]
+async fn list_overlays() {
+    let mut server = TestServer::start().await;
+    server.upload(upload_request("top.bin", &bitstream())).await.unwrap();
+    server.upload(upload_request("top.dtbo", &overlay())).await.unwrap();
+    server.upload(upload_request("extra.dtbo", &overlay())).await.unwrap();
+    let client = &mut server.client;
+
+    let response = client.list_overlays(Empty {}).await.unwrap().into_inner();
+    assert!(response.overlays.is_empty());
+    assert!(response.slots.is_empty());
+
+    let request = RegisterAccelRequest {
+        accel_name: "top".into(),
+        bin_file: "top.bin".into(),
+        dtbo_file: "top.dtbo".into(),
+        json_file: String::new(),
+        overwrite: false,
+    };
+    client.register_accel(request).await.unwrap();
+    let slot = client.load(LoadRequest { name: "top".into() }).await.unwrap().into_inner().slot;
+    client.load_dtbo(LoadDtboRequest { name: "extra.dtbo".into() }).await.unwrap();
+
+    let response = client.list_overlays(Empty {}).await.unwrap().into_inner();
+    let names: Vec<&str> = response.overlays.iter().map(|o| o.name.as_str()).collect();
+    assert_eq!(names, ["extra", "top"]);
+    assert_eq!(response.overlays[0].status, "applied");
+    assert!(response.overlays[0].path.ends_with("extra.dtbo"));
+    assert_eq!(response.slots.len(), 1);
+    assert_eq!((response.slots[0].slot, response.slots[0].accel.as_str()), (slot, "top"));
+
+    client.unload(UnloadRequest { slot }).await.unwrap();
+    let response = client.list_overlays(Empty {}).await.unwrap().into_inner();
+    assert_eq!(response.overlays.len(), 1);
+    assert!(response.slots.is_empty());
+}
+
+#[tokio::test]
+async fn audit_records_denied_requests() {
+    let audit_dir = TempDir::new().unwrap();
+    let audit_path = audit_dir.path().join("audit.log");
+    let mut config = Config::default();
+    config.audit.path = Some(audit_path.to_string_lossy().into_owned());
+    let tokens = auth::parse_tokens("read-only reader-token reader").unwrap();
+    let mut server = TestServer::with_tokens(config, Some(tokens)).await;
+
+    let request = with_token("reader-token", LoadBitstreamRequest { name: "top.bin".into() });
+    let status = server.client.load_bitstream(request).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let log = std::fs::read_to_string(&audit_path).unwrap();
+    let record: serde_json::Value =
+        serde_json::from_str(log.lines().next().unwrap()).unwrap();
+    assert_eq!(record["rpc"], "LoadBitstream");
+    assert_eq!(record["identity"], "reader");
+    assert_eq!(record["result"], "error");
+    assert_eq!(record["code"], "PermissionDenied");
+    assert_eq!(record["args"]["name"], "top.bin");
+}
+
+#[tokio::test]
+async fn audit_records_denied_writes() {
+    let audit_dir = TempDir::new().unwrap();
+    let audit_path = audit_dir.path().join("audit.log");
+    let mut config = Config::default();
+    config.audit.path = Some(audit_path.to_string_lossy().into_owned());
+    config.audit.writes = true;
+    let tokens = auth::parse_tokens("read-only reader-token reader").unwrap();
+    let mut server = TestServer::with_tokens(config, Some(tokens)).await;
+
+    let request = WriteMemURequest { id: 0, offset: 0x10, data: 1, size: 4 };
+    let status = server.client.write_mem_u(with_token("reader-token", request)).await.unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    let request = ExecuteBatchRequest {
+        ops: vec![batch_op(0, BatchOpType::Write, 0, 1, 0)],
+        stop_on_error: true,
+    };
+    let status = server.client.execute_batch(with_token("reader-token", request)).await;
+    assert_eq!(status.unwrap_err().code(), Code::PermissionDenied);
+
+    let log = std::fs::read_to_string(&audit_path).unwrap();
+    let records: Vec<serde_json::Value> =
+        log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["rpc"], "WriteMemU");
+    assert_eq!(records[0]["identity"], "reader");
+    assert_eq!(records[0]["code"], "PermissionDenied");
+    assert_eq!(records[0]["args"]["offset"], 0x10);
+    assert_eq!(records[1]["rpc"], "ExecuteBatch");
+    assert_eq!(records[1]["code"], "PermissionDenied");
+}
+
+#[test]
+fn command_line_overrides_config_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("config.toml");
+    let text = "[server]\nport = 9000\nexternal = true\n\
+                [firmware]\ndir = \"/srv/firmware\"\n\
+                [security]\ntoken_file = \"/etc/tokens\"\n";
+    std::fs::write(&path, text).unwrap();
+    let path = path.to_string_lossy().into_owned();
+
+    // options that are not given keep the file's values
+    let args = Args::parse_from(["jelly-fpga-server", "--config", &path]);
+    let mut config = Config::load(&path, true).unwrap();
+    merge_args(&mut config, &args);
+    assert_eq!(config.server.port, 9000);
+    assert_eq!(config.firmware.dir, "/srv/firmware");
+
+    let args = Args::parse_from([
+        "jelly-fpga-server",
+        "--config",
+        &path,
+        "--port",
+        "9100",
+        "--firmware-dir",
+        "/tmp/firmware",
+        "--token-file",
+        "/tmp/tokens",
+        "--backend",
+        "sim",
+    ]);
+    let mut config = Config::load(&path, true).unwrap();
+    merge_args(&mut config, &args);
+    assert_eq!(config.server.port, 9100);
+    assert!(config.server.external);
+    assert_eq!(config.firmware.dir, "/tmp/firmware");
+    assert_eq!(config.security.token_file.as_deref(), Some("/tmp/tokens"));
+    assert_eq!(config.platform.backend, Backend::Sim);
+}